@@ -0,0 +1,200 @@
+//! End-to-end throughput of the socket server: an iosync server runs in a
+//! background thread and clients time GET, SET and SET-then-GET round trips
+//! over its Unix socket, for several payload sizes and client counts, and GET
+//! against reading the server's memory-mapped copy of the text.
+//!
+//! Run with `cargo bench`; sync lines the server emits go to stderr, so
+//! redirect it (`cargo bench 2>/dev/null`) to keep the report readable.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use ssh_clipboard::{
+    init_log, ClipboardClient, ClipboardServer, ConflictStrategy, Level, NormOpts, Options,
+    UnixTransport, WireFormat,
+};
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+const PAYLOAD_SIZES: &[(&str, usize)] = &[("16B", 16), ("4KiB", 4 * 1024), ("1MiB", 1024 * 1024)];
+const CLIENT_COUNTS: &[usize] = &[1, 4, 16];
+
+fn options(dir: &TempDir) -> Options {
+    Options {
+        socket_path: dir.path().join("iosync.sock").display().to_string(),
+        abstract_socket: false,
+        socket_mode: 0o600,
+        tcp_addr: None,
+        metrics_socket: None,
+        pid_path: dir.path().join("iosync.pid"),
+        state_path: None,
+        image_out: None,
+        audit_log: None,
+        log_path: None,
+        log_max_bytes: 0,
+        log_backups: 0,
+        log_level: Level::Error,
+        poll_interval: Duration::from_millis(100),
+        debounce: Duration::ZERO,
+        history_size: 5,
+        clipboard_ttl: None,
+        heartbeat_interval: None,
+        heartbeat_timeout: None,
+        auth_key: None,
+        allow_uids: Vec::new(),
+        retry_interval: Duration::from_millis(10),
+        retry_count: 200,
+        verbose: false,
+        max_bytes: 2 * 1024 * 1024,
+        compress_threshold: 64 * 1024,
+        sync_prefix: "CLIPBOARD_SYNC:".to_string(),
+        wire_format: WireFormat::Json,
+        skip_patterns: Vec::new(),
+        mask_patterns: Vec::new(),
+        normalize: NormOpts::default(),
+        tracking_params: Vec::new(),
+        conflict_strategy: ConflictStrategy::LastWriterWins,
+        rate_limit: 0,
+        global_rate_limit: 0,
+        max_bandwidth_bps: None,
+        shutdown_timeout: Duration::from_secs(1),
+        takeover: false,
+        mmap_path: None,
+        dbus: false,
+    }
+}
+
+/// Start a server in a temporary directory, with options adjusted by
+/// `configure`, and return the options to reach it. The server thread runs
+/// until the process exits.
+fn start_server(configure: impl FnOnce(&mut Options)) -> (TempDir, Options) {
+    init_log(None, 0, 0, Level::Error);
+    let dir = tempfile::tempdir().unwrap();
+    let mut server_opts = options(&dir);
+    configure(&mut server_opts);
+    let server = ClipboardServer::new(server_opts);
+    thread::spawn(move || server.run());
+    let opts = options(&dir);
+    // Wait for the socket to come up before timing anything.
+    client(&opts).ping().unwrap();
+    (dir, opts)
+}
+
+fn client(opts: &Options) -> ClipboardClient<UnixTransport> {
+    ClipboardClient::new(UnixTransport::new(opts), opts)
+}
+
+/// Two payloads of `size` bytes that differ, so alternating between them makes
+/// every SET a real change.
+fn payloads(size: usize) -> [String; 2] {
+    ["a".repeat(size), format!("b{}", "a".repeat(size - 1))]
+}
+
+fn bench_single_client(c: &mut Criterion) {
+    let (_dir, opts) = start_server(|_| {});
+    let client = client(&opts);
+    for &(label, size) in PAYLOAD_SIZES {
+        let [a, b] = payloads(size);
+        let mut group = c.benchmark_group(format!("single_client/{}", label));
+        group.throughput(Throughput::Elements(1));
+        if size >= 1024 * 1024 {
+            group.sample_size(20);
+        }
+
+        let mut flip = false;
+        group.bench_function("set", |bencher| {
+            bencher.iter(|| {
+                flip = !flip;
+                client.set(if flip { &a } else { &b }).unwrap();
+            })
+        });
+
+        client.set(&a).unwrap();
+        group.bench_function("get", |bencher| bencher.iter(|| client.get().unwrap()));
+
+        group.bench_function("round_trip", |bencher| {
+            bencher.iter(|| {
+                flip = !flip;
+                let text = if flip { &a } else { &b };
+                client.set(text).unwrap();
+                assert_eq!(client.get().unwrap().len(), text.len());
+            })
+        });
+        group.finish();
+    }
+}
+
+/// SETs from several clients at once; reported throughput is total messages
+/// per second across all of them.
+fn bench_concurrent_clients(c: &mut Criterion) {
+    let (_dir, opts) = start_server(|_| {});
+    let [a, b] = payloads(4 * 1024);
+    let mut group = c.benchmark_group("concurrent_set/4KiB");
+    for &clients in CLIENT_COUNTS {
+        group.throughput(Throughput::Elements(clients as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(clients),
+            &clients,
+            |bencher, &n| {
+                bencher.iter_custom(|iters| {
+                    let start = Instant::now();
+                    thread::scope(|scope| {
+                        for id in 0..n {
+                            let (opts, a, b) = (&opts, &a, &b);
+                            scope.spawn(move || {
+                                let client = client(opts);
+                                for i in 0..iters {
+                                    client
+                                        .set(if (i + id as u64).is_multiple_of(2) {
+                                            a
+                                        } else {
+                                            b
+                                        })
+                                        .unwrap();
+                                }
+                            });
+                        }
+                    });
+                    start.elapsed()
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+/// GET over the socket against the same client reading the mapped file.
+fn bench_mapped_get(c: &mut Criterion) {
+    let map_dir = tempfile::tempdir().unwrap();
+    let map_path = map_dir.path().join("clipboard.map");
+    let (dir, opts) = start_server(|opts| opts.mmap_path = Some(map_path.clone()));
+    let socket_client = client(&opts);
+    let mapped_client = client(&Options {
+        mmap_path: Some(map_path.clone()),
+        ..options(&dir)
+    });
+    for &(label, size) in PAYLOAD_SIZES {
+        let [text, _] = payloads(size);
+        socket_client.set(&text).unwrap();
+        // Wait for the server to copy the new text into the mapping.
+        while mapped_client.get().unwrap().len() != size {
+            thread::sleep(Duration::from_millis(1));
+        }
+        let mut group = c.benchmark_group(format!("mapped_get/{}", label));
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_function("socket", |bencher| {
+            bencher.iter(|| socket_client.get().unwrap())
+        });
+        group.bench_function("mmap", |bencher| {
+            bencher.iter(|| mapped_client.get().unwrap())
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(
+    benches,
+    bench_single_client,
+    bench_concurrent_clients,
+    bench_mapped_get
+);
+criterion_main!(benches);
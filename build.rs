@@ -0,0 +1,88 @@
+use clap::CommandFactory;
+use clap_complete::Shell;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::SystemTime;
+
+#[allow(dead_code)]
+#[path = "src/cli.rs"]
+mod cli;
+
+/// Record the git hash and build date for `--version`, then generate the C
+/// header for the FFI module next to the built library, e.g.
+/// `target/debug/ssh_clipboard.h`, and shell completions in
+/// `target/debug/completions`.
+fn main() {
+    println!("cargo:rerun-if-changed=src/ffi.rs");
+    println!("cargo:rerun-if-changed=src/cli.rs");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/index");
+    println!("cargo:rustc-env=SSH_CLIPBOARD_GIT_HASH={}", git_hash());
+    let date = humantime::format_rfc3339_seconds(SystemTime::now()).to_string();
+    println!("cargo:rustc-env=SSH_CLIPBOARD_BUILD_DATE={}", &date[..10]);
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    // OUT_DIR is <target>/<profile>/build/<pkg>-<hash>/out.
+    let profile_dir = out_dir.ancestors().nth(3).unwrap();
+    generate_header(profile_dir);
+    generate_completions(&profile_dir.join("completions"));
+}
+
+/// The short hash of the checked-out commit, or "unknown" outside a git
+/// checkout or without git.
+fn git_hash() -> String {
+    Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_string())
+        .filter(|hash| !hash.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+fn generate_header(profile_dir: &Path) {
+    let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let config = cbindgen::Config {
+        language: cbindgen::Language::C,
+        include_guard: Some("SSH_CLIPBOARD_H".to_string()),
+        parse: cbindgen::ParseConfig {
+            parse_deps: false,
+            ..Default::default()
+        },
+        export: cbindgen::ExportConfig {
+            include: vec!["SshClipboardClient".to_string()],
+            item_types: vec![
+                cbindgen::ItemType::Functions,
+                cbindgen::ItemType::OpaqueItems,
+            ],
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    cbindgen::Builder::new()
+        .with_crate(crate_dir)
+        .with_config(config)
+        .generate()
+        .expect("Failed to generate the C header")
+        .write_to_file(profile_dir.join("ssh_clipboard.h"));
+}
+
+/// Bash, zsh and fish completions for `ssh-clipboard` and the `iosync` and
+/// `xclip` links.
+fn generate_completions(dir: &Path) {
+    fs::create_dir_all(dir).expect("Failed to create the completions directory");
+    for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+        for mut command in [
+            cli::Cli::command(),
+            cli::Iosync::command(),
+            cli::Xclip::command(),
+        ] {
+            let name = command.get_name().to_string();
+            clap_complete::generate_to(shell, &mut command, name, dir)
+                .expect("Failed to generate shell completions");
+        }
+    }
+}
@@ -0,0 +1,317 @@
+//! Command-line interface. Also compiled into the build script, which generates
+//! shell completions from it, so it only depends on clap and std.
+
+use clap::{Args, Parser};
+use std::path::PathBuf;
+
+pub const SOCKET_ENV: &str = "SSH_CLIPBOARD_SOCKET";
+pub const LOG_ENV: &str = "SSH_CLIPBOARD_LOG";
+pub const POLL_MS_ENV: &str = "SSH_CLIPBOARD_POLL_MS";
+pub const AUTH_KEY_ENV: &str = "SSH_CLIPBOARD_KEY";
+
+/// Sync the clipboard between a headless Linux box and a macOS desktop over SSH.
+#[derive(Parser)]
+#[command(name = "ssh-clipboard")]
+// Parsed once per run, so the size of the larger variant doesn't matter.
+#[allow(clippy::large_enum_variant)]
+pub enum Cli {
+    /// Run the iosync server.
+    Iosync(IosyncArgs),
+    /// Read or write the clipboard through a running server, like xclip.
+    Xclip(XclipArgs),
+}
+
+/// The iosync server, when the binary is invoked through a link named `iosync`.
+#[derive(Parser)]
+#[command(name = "iosync", about = "Run the iosync server")]
+pub struct Iosync {
+    #[command(flatten)]
+    pub args: IosyncArgs,
+}
+
+/// The xclip client, when the binary is invoked through a link named `xclip`.
+#[derive(Parser)]
+#[command(
+    name = "xclip",
+    about = "Read or write the clipboard through a running server, like xclip"
+)]
+pub struct Xclip {
+    #[command(flatten)]
+    pub args: XclipArgs,
+}
+
+/// Flags shared by the server and the client.
+#[derive(Args, Default)]
+pub struct SharedArgs {
+    /// Config file [default: ~/.config/ssh-clipboard/config.toml]
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    /// Unix socket path; a leading '@' names an abstract socket. "auto", the
+    /// default, picks one per SSH session from $SSH_CONNECTION
+    #[arg(long, value_name = "PATH", env = SOCKET_ENV)]
+    pub socket: Option<String>,
+    /// Use the Linux abstract socket namespace
+    #[arg(long = "abstract")]
+    pub abstract_socket: bool,
+    /// Use TCP on host:port instead of the Unix socket
+    #[arg(long, value_name = "HOST:PORT")]
+    pub tcp: Option<String>,
+    /// Memory-mapped file the server mirrors the clipboard text into, for
+    /// clients on the same machine to read without the socket
+    #[arg(long, value_name = "PATH")]
+    pub mmap: Option<PathBuf>,
+    /// Hex key for HMAC-signed commands
+    #[arg(long, value_name = "HEX", env = AUTH_KEY_ENV, hide_env_values = true)]
+    pub auth_key: Option<String>,
+    /// Log file
+    #[arg(long, value_name = "PATH", env = LOG_ENV)]
+    pub log: Option<PathBuf>,
+    /// Disable logging
+    #[arg(long, conflicts_with = "log")]
+    pub no_log: bool,
+    /// Rotate the log once it reaches this size
+    #[arg(long, value_name = "BYTES")]
+    pub log_max_bytes: Option<u64>,
+    /// Number of rotated log files to keep
+    #[arg(long, value_name = "N")]
+    pub log_backups: Option<usize>,
+    /// Minimum level to log
+    #[arg(long, value_name = "LEVEL", ignore_case = true,
+          value_parser = ["debug", "info", "warn", "error"])]
+    pub log_level: Option<String>,
+}
+
+/// Flags of the iosync server.
+#[derive(Args, Default)]
+pub struct IosyncArgs {
+    #[command(flatten)]
+    pub shared: SharedArgs,
+    /// Permissions of the socket file, in octal
+    #[arg(long, value_name = "MODE", value_parser = parse_octal)]
+    pub socket_mode: Option<u32>,
+    /// Unix socket serving Prometheus metrics [default: <socket>.metrics]
+    #[arg(long, value_name = "PATH")]
+    pub metrics_socket: Option<String>,
+    /// Don't serve metrics
+    #[arg(long, conflicts_with = "metrics_socket")]
+    pub no_metrics: bool,
+    /// PID file guarding against a second server on the same socket
+    /// [default: <socket>.pid]
+    #[arg(long, value_name = "PATH")]
+    pub pid_file: Option<PathBuf>,
+    /// Save the clipboard here to restore it after a restart
+    /// [default: ~/.cache/ssh-clipboard/last.json]
+    #[arg(long, value_name = "PATH")]
+    pub state_file: Option<PathBuf>,
+    /// Also write images set with SET_IMAGE to this file; "-" is stdout
+    #[arg(long, value_name = "PATH")]
+    pub image_out: Option<PathBuf>,
+    /// Append a JSON line, with the content's hash but not the content, for
+    /// every change made through the socket
+    /// [default: ~/.local/share/ssh-clipboard/audit.log]
+    #[arg(long, value_name = "PATH")]
+    pub audit_log: Option<PathBuf>,
+    /// Disable the audit log
+    #[arg(long, conflicts_with = "audit_log")]
+    pub no_audit_log: bool,
+    /// Clipboard polling interval when idle; polling is faster after a change
+    #[arg(long, value_name = "MS", env = POLL_MS_ENV)]
+    pub poll_ms: Option<u64>,
+    /// Wait for the clipboard to settle this long before syncing
+    #[arg(long, value_name = "MS")]
+    pub debounce_ms: Option<u64>,
+    /// Number of history entries to keep
+    #[arg(long, value_name = "N")]
+    pub history_size: Option<usize>,
+    /// Clear the clipboard this many seconds after the last change
+    #[arg(long, value_name = "SECONDS")]
+    pub clipboard_ttl_s: Option<u64>,
+    /// Print a heartbeat line this often for the Mac side to watch; 0 disables
+    #[arg(long, value_name = "SECONDS")]
+    pub heartbeat_s: Option<u64>,
+    /// On the Mac, exit once the server has been silent this long; 0 disables
+    #[arg(long, value_name = "SECONDS")]
+    pub heartbeat_timeout_s: Option<u64>,
+    /// Extra UIDs allowed to connect
+    #[arg(long, value_name = "UID", value_delimiter = ',')]
+    pub allow_uid: Vec<u32>,
+    /// Largest payload to sync
+    #[arg(long, value_name = "BYTES")]
+    pub max_bytes: Option<usize>,
+    /// Compress payloads larger than this on the wire
+    #[arg(long, value_name = "BYTES")]
+    pub compress_threshold: Option<usize>,
+    /// Marker for sync lines
+    #[arg(long, value_name = "PREFIX")]
+    pub prefix: Option<String>,
+    /// Encoding of sync lines
+    #[arg(long, value_name = "FORMAT", value_parser = ["json", "msgpack"])]
+    pub format: Option<String>,
+    /// Don't sync text matching this regex; repeatable
+    #[arg(long, value_name = "REGEX")]
+    pub skip_pattern: Vec<String>,
+    /// Sync text matching this regex as [REDACTED]; repeatable
+    #[arg(long, value_name = "REGEX")]
+    pub mask_pattern: Vec<String>,
+    /// Convert \r\n line endings to \n before syncing
+    #[arg(long)]
+    pub normalize_newlines: bool,
+    /// Strip leading and trailing whitespace before syncing
+    #[arg(long)]
+    pub trim: bool,
+    /// Remove tracking parameters such as utm_source from URLs copied on the Mac
+    #[arg(long)]
+    pub strip_tracking: bool,
+    /// Most sets and clears a client may send per second; 0 disables the limit
+    #[arg(long, value_name = "N")]
+    pub rate_limit: Option<u32>,
+    /// Most sets and clears all clients together may send per second; 0
+    /// disables the limit
+    #[arg(long, value_name = "N")]
+    pub global_rate_limit: Option<u32>,
+    /// Limit sync lines and socket replies to this many bytes per second; 0
+    /// disables the limit
+    #[arg(long, value_name = "BYTES")]
+    pub max_bandwidth_bps: Option<u64>,
+    /// On SIGTERM, wait this long for connections in progress to finish
+    #[arg(long, value_name = "SECONDS")]
+    pub shutdown_timeout_s: Option<u64>,
+    /// Also serve the clipboard on the D-Bus session bus as
+    /// io.github.tapthaker.SshClipboard (Linux only)
+    #[arg(long)]
+    pub dbus: bool,
+    /// Take the socket over from the running server, which then drains and
+    /// exits, instead of binding it; for upgrades without downtime
+    #[arg(long)]
+    pub takeover: bool,
+    /// Check the socket, log file, clipboard and SSH session, print what is
+    /// wrong and how to fix it, and exit; with 0 only if everything passed
+    #[arg(long)]
+    pub diagnose: bool,
+    /// Print a Homebrew formula for this version and exit. Its SHA-256 is that
+    /// of TARBALL, the release's source archive, or a placeholder
+    #[arg(long, value_name = "TARBALL", num_args = 0..=1)]
+    pub print_formula: Option<Option<PathBuf>>,
+    /// Install a launchd agent that runs iosync with the other flags given here
+    /// at login, then load it (macOS only)
+    #[arg(long, conflicts_with_all = ["uninstall_launchd", "takeover"])]
+    pub install_launchd: bool,
+    /// Unload and remove the agent --install-launchd installed (macOS only)
+    #[arg(long)]
+    pub uninstall_launchd: bool,
+    /// How to resolve a change made on both sides at once
+    #[arg(long, value_name = "STRATEGY", value_parser = ["last-writer-wins", "prefer-local"])]
+    pub conflict: Option<String>,
+}
+
+/// Flags of the xclip client. Like xclip, it writes stdin to the clipboard
+/// unless `-o` is given.
+#[derive(Args, Default)]
+pub struct XclipArgs {
+    #[command(flatten)]
+    pub shared: SharedArgs,
+    /// Wait between connection attempts
+    #[arg(long, value_name = "MS")]
+    pub retry_ms: Option<u64>,
+    /// Retry a failed connection this many times
+    #[arg(long, value_name = "N")]
+    pub retry_count: Option<u32>,
+    /// Set the clipboard from stdin; the default
+    #[arg(short = 'i', conflicts_with = "output")]
+    pub input: bool,
+    /// Print the clipboard instead of setting it
+    #[arg(short = 'o')]
+    pub output: bool,
+    /// How -o prints the clipboard: its text, or the whole message with its
+    /// content type, checksum and sequence number as JSON
+    #[arg(long, value_name = "FORMAT", value_parser = ["text", "json"], requires = "output")]
+    pub format: Option<String>,
+    /// Transfer raw bytes of this content type; with -o, a comma-separated list
+    /// in order of preference. TARGETS with -o lists the types
+    #[arg(short = 't', value_name = "TYPE")]
+    pub target: Option<String>,
+    /// Selection to use: any prefix of "clipboard" or "primary"
+    #[arg(long, visible_alias = "sel", value_name = "SELECTION")]
+    pub selection: Option<String>,
+    /// Print the nth most recent synced entry
+    #[arg(long, value_name = "N")]
+    pub history: Option<usize>,
+    /// List the history entries containing this text, ignoring case, with
+    /// their index for --history
+    #[arg(long, value_name = "QUERY", conflicts_with_all = ["output", "input"])]
+    pub search: Option<String>,
+    /// Match --search as a regex instead
+    #[arg(long, requires = "search")]
+    pub regex: bool,
+    /// Write every history entry, with its timestamp, to a JSON file
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["output", "input"])]
+    pub export: Option<PathBuf>,
+    /// Add the entries of a file written by --export to the history
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["output", "input", "export"])]
+    pub import: Option<PathBuf>,
+    /// Read or write this named slot, like a vim register, instead of the
+    /// clipboard
+    #[arg(long, value_name = "NAME")]
+    pub slot: Option<String>,
+    /// List the selections and slots holding text, with their sizes
+    #[arg(long)]
+    pub list_slots: bool,
+    /// Empty the clipboard
+    #[arg(long)]
+    pub clear: bool,
+    /// Empty the clipboard, the history, PRIMARY and the slots, as before
+    /// sharing your screen
+    #[arg(long, conflicts_with = "clear")]
+    pub wipe: bool,
+    /// Print the start of the clipboard, its size and its content type on one
+    /// line, for a shell prompt or tmux status line
+    #[arg(long, conflicts_with_all = ["output", "input"])]
+    pub peek: bool,
+    /// Print the server's uptime, sync count, bytes sent and received, and the
+    /// time since the last sync on one line
+    #[arg(long, conflicts_with_all = ["output", "input", "peek"])]
+    pub stats: bool,
+    /// Print the clipboard text as a QR code, e.g. to open a URL on a phone
+    #[arg(long, conflicts_with_all = ["output", "input", "peek"])]
+    pub qr: bool,
+    /// With --peek, print at most this many characters [default: 80]
+    #[arg(long, value_name = "N", requires = "peek")]
+    pub max_chars: Option<usize>,
+    /// Wait for the clipboard to change and print a unified diff of the change
+    #[arg(long, conflicts_with_all = ["output", "wait"])]
+    pub diff: bool,
+    /// Print each command sent to the server and its reply to stderr
+    #[arg(long)]
+    pub verbose: bool,
+    /// Print the command instead of changing the clipboard; reads still happen
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Print the round-trip latency to the server
+    #[arg(long)]
+    pub ping: bool,
+    /// Like --ping, but give up on a server that doesn't answer within a few
+    /// seconds and exit with 1 on any failure; for health checks
+    #[arg(long, conflicts_with = "ping")]
+    pub test: bool,
+    /// Behave as neovim's clipboard provider expects: print and read text
+    /// verbatim, treat an unavailable PRIMARY as empty, and exit with 1 on errors
+    #[arg(long)]
+    pub neovim_compat: bool,
+    /// Accepted for compatibility with xclip; has no effect
+    #[arg(long, hide = true)]
+    pub quiet: bool,
+    /// Accepted for compatibility with xclip; has no effect
+    #[arg(long, hide = true)]
+    pub silent: bool,
+    /// Use tmux's paste buffer instead of stdin and stdout
+    #[arg(long)]
+    pub tmux: bool,
+    /// Wait up to this long for the clipboard to change, then print it
+    #[arg(long, value_name = "MS", requires = "output")]
+    pub wait: Option<u64>,
+}
+
+/// Helper: parse an octal file mode such as `660`.
+fn parse_octal(value: &str) -> Result<u32, String> {
+    u32::from_str_radix(value, 8).map_err(|_| format!("invalid socket mode: {}", value))
+}
@@ -0,0 +1,112 @@
+//! C API over [`ClipboardClient`], for tools that would rather not shell out to
+//! xclip. The header, `ssh_clipboard.h`, is generated by the build script and
+//! written next to the library.
+
+use crate::{ClipboardClient, UnixTransport, DEFAULT_SOCKET_PATH};
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::ptr;
+
+/// Opaque handle to a client of the iosync socket.
+pub struct SshClipboardClient {
+    client: ClipboardClient<UnixTransport>,
+}
+
+/// Create a client for the socket at `socket_path`, or the default socket when
+/// it is NULL. `auth_key` is the hex key the server was started with, or NULL.
+/// Returns NULL if either string is invalid. Free the client with
+/// `ssh_clipboard_free`.
+///
+/// # Safety
+///
+/// `socket_path` and `auth_key` must each be NULL or a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn ssh_clipboard_client_new(
+    socket_path: *const c_char,
+    auth_key: *const c_char,
+) -> *mut SshClipboardClient {
+    let socket_path = match optional_str(socket_path) {
+        Ok(path) => path.unwrap_or(DEFAULT_SOCKET_PATH),
+        Err(()) => return ptr::null_mut(),
+    };
+    let auth_key = match optional_str(auth_key) {
+        Ok(Some(key)) => match hex::decode(key) {
+            Ok(key) => Some(key),
+            Err(_) => return ptr::null_mut(),
+        },
+        Ok(None) => None,
+        Err(()) => return ptr::null_mut(),
+    };
+    let transport = UnixTransport::with_path(socket_path);
+    let client = ClipboardClient::with_auth_key(transport, auth_key);
+    Box::into_raw(Box::new(SshClipboardClient { client }))
+}
+
+/// Read the clipboard text. Returns NULL on error; otherwise free the string
+/// with `ssh_clipboard_string_free`.
+///
+/// # Safety
+///
+/// `client` must come from `ssh_clipboard_client_new` and not have been freed.
+#[no_mangle]
+pub unsafe extern "C" fn ssh_clipboard_get(client: *const SshClipboardClient) -> *mut c_char {
+    let Some(client) = client.as_ref() else {
+        return ptr::null_mut();
+    };
+    match client.client.get().map(CString::new) {
+        Ok(Ok(text)) => text.into_raw(),
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Set the clipboard text. Returns 0 on success, otherwise the exit code xclip
+/// would have used for the same error.
+///
+/// # Safety
+///
+/// `client` must come from `ssh_clipboard_client_new` and not have been freed,
+/// and `text` must be a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn ssh_clipboard_set(
+    client: *const SshClipboardClient,
+    text: *const c_char,
+) -> c_int {
+    let (Some(client), Ok(Some(text))) = (client.as_ref(), optional_str(text)) else {
+        return 1;
+    };
+    match client.client.set(text) {
+        Ok(()) => 0,
+        Err(e) => e.exit_code(),
+    }
+}
+
+/// Free a client. NULL is ignored.
+///
+/// # Safety
+///
+/// `client` must come from `ssh_clipboard_client_new` and not have been freed.
+#[no_mangle]
+pub unsafe extern "C" fn ssh_clipboard_free(client: *mut SshClipboardClient) {
+    if !client.is_null() {
+        drop(Box::from_raw(client));
+    }
+}
+
+/// Free a string returned by `ssh_clipboard_get`. NULL is ignored.
+///
+/// # Safety
+///
+/// `text` must come from `ssh_clipboard_get` and not have been freed.
+#[no_mangle]
+pub unsafe extern "C" fn ssh_clipboard_string_free(text: *mut c_char) {
+    if !text.is_null() {
+        drop(CString::from_raw(text));
+    }
+}
+
+/// Helper: borrow a C string, treating NULL as `None`. Fails on invalid UTF-8.
+unsafe fn optional_str<'a>(text: *const c_char) -> Result<Option<&'a str>, ()> {
+    if text.is_null() {
+        return Ok(None);
+    }
+    CStr::from_ptr(text).to_str().map(Some).map_err(|_| ())
+}
@@ -1,225 +1,1230 @@
-use arboard::Clipboard;
-use serde::{Deserialize, Serialize};
+mod cli;
+
+use clap::{CommandFactory, FromArgMatches};
+use cli::{Cli, Iosync, IosyncArgs, SharedArgs, Xclip, XclipArgs};
+use qrcode::render::unicode::Dense1x2;
+use qrcode::types::QrError;
+use qrcode::{EcLevel, QrCode};
+use regex::Regex;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use similar::TextDiff;
+use ssh_clipboard::{
+    init_log, launchd_plist, log, parse_types, session_socket_path, ClipboardClient,
+    ClipboardServer, ConflictStrategy, Error, Level, Message, NormOpts, Options, Status,
+    TcpTransport, Transport, UnixTransport, WireFormat, DEFAULT_LOG_BACKUPS, DEFAULT_LOG_MAX_BYTES,
+    DEFAULT_LOG_PATH, DEFAULT_METRICS_SOCKET_PATH, DEFAULT_SOCKET_MODE, DEFAULT_SOCKET_PATH,
+    LAUNCHD_LABEL, VERSION,
+};
 use std::env;
-use std::io::{self, BufRead, BufReader, Read, Write};
-use std::net::Shutdown;
-use std::os::unix::net::{UnixListener, UnixStream};
-use std::path::Path;
-use std::sync::{Arc, Mutex};
-use std::thread;
-use std::time::Duration;
-
-const SOCKET_PATH: &str = "/tmp/iosync_socket";
-const LOG_PATH: &str = "/tmp/ssh-clipboard.log";
-
-//Write a macro to log to a file
-macro_rules! log {
-    ($($arg:tt)*) => {
-        {
-            let mut file = std::fs::OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(LOG_PATH)
-                .expect("Failed to open log file");
-            writeln!(file, $($arg)*).expect("Failed to write to log file");
-        }
-    };
-}
-
-#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
-struct Message {
-    content: String,
-}
-
-/// Helper: remove old socket if it exists.
-fn cleanup_socket() {
-    if Path::new(SOCKET_PATH).exists() {
-        let _ = std::fs::remove_file(SOCKET_PATH);
-    }
-}
-
-fn run_iosync_mode_on_linux(last_message: Arc<Mutex<String>>) -> io::Result<()> {
-    cleanup_socket();
-    let listener = UnixListener::bind(SOCKET_PATH)?;
-    log!("Listening on the Unix socket: {}", SOCKET_PATH);
-
-    // Server loop: accept connections on the Unix socket.
-    for stream in listener.incoming() {
-        match stream {
-            Ok(mut stream) => {
-                let last_message_conn = Arc::clone(&last_message);
-                // Read the command from the client.
-                let mut reader = BufReader::new(&mut stream);
-                let mut command = String::new();
-                if let Err(e) = reader.read_line(&mut command) {
-                    log!("Failed to read from stream: {}", e);
-                }
-                command = command.trim().to_string();
-                log!("Received command: {}", command);
-
-                // Command protocol:
-                // "GET" returns the current clipboard content.
-                // "SET <text>" updates the clipboard.
-                if command == "GET" {
-                    let last = last_message_conn.lock().unwrap();
-                    let reply = last.clone();
-                    let _ = stream.write_all(reply.as_bytes());
-                } else if command.starts_with("SET ") {
-                    let new_text = command["SET ".len()..].to_string();
-                    let msg = Message {
-                        content: new_text.clone(),
-                    };
-                    let mut last = last_message_conn.lock().unwrap();
-                    if *last != msg.content {
-                        if let Ok(msg_str) = serde_json::to_string(&msg) {
-                            *last = msg.content.clone();
-                            eprintln!("CLIPBOARD-SYNC:{}", msg_str);
-                            log!("CLIPBOARD-SYNC:{}", msg_str);
-                        }
-                    }
-                    let _ = stream.write_all(b"OK");
-                } else {
-                    let _ = stream.write_all(b"Unknown command");
-                }
-                let _ = stream.shutdown(Shutdown::Both);
-            }
-            Err(e) => {
-                log!("Socket connection failed: {}", e);
+use std::io::{self, BufRead, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const CONFIG_FILE_NAME: &str = "config.toml";
+const STATE_FILE_NAME: &str = "last.json";
+const AUDIT_LOG_NAME: &str = "audit.log";
+const DEFAULT_PID_PATH: &str = "/tmp/ssh-clipboard.pid";
+const DEFAULT_POLL_MS: u64 = 500;
+const POLL_MS_RANGE: std::ops::RangeInclusive<u64> = 10..=5000;
+const DEFAULT_HISTORY_SIZE: usize = 50;
+const DEFAULT_DEBOUNCE_MS: u64 = 50;
+const DEFAULT_MAX_BYTES: usize = 1024 * 1024;
+const DEFAULT_COMPRESS_THRESHOLD: usize = 4 * 1024;
+const DEFAULT_RETRY_MS: u64 = 100;
+const DEFAULT_HEARTBEAT_S: u64 = 15;
+const DEFAULT_HEARTBEAT_TIMEOUT_S: u64 = 60;
+const DEFAULT_RATE_LIMIT: u32 = 10;
+const DEFAULT_GLOBAL_RATE_LIMIT: u32 = 100;
+const DEFAULT_SHUTDOWN_TIMEOUT_S: u64 = 10;
+const DEFAULT_SYNC_PREFIX: &str = "CLIPBOARD_SYNC:";
+/// Query parameters `--strip-tracking` removes unless the config lists others.
+const DEFAULT_TRACKING_PARAMS: &[&str] = &[
+    "utm_source",
+    "utm_medium",
+    "utm_campaign",
+    "utm_term",
+    "utm_content",
+    "fbclid",
+    "gclid",
+    "mc_eid",
+];
+/// The `--socket` value that asks for the per-session socket explicitly.
+const SOCKET_AUTO: &str = "auto";
+/// How long `--diff` waits for the clipboard to change.
+const DIFF_WAIT: Duration = Duration::from_secs(600);
+/// The pseudo content type xclip uses to list the available types.
+const TARGETS: &str = "TARGETS";
+/// Characters of the clipboard `xclip --peek` prints by default.
+const DEFAULT_PEEK_CHARS: usize = 80;
+/// The most bytes of arbitrary text a QR code holds, at the lowest error
+/// correction level. Digits and capitals pack more densely.
+const QR_MAX_BYTES: usize = 2953;
+/// How long `xclip --test` waits for the server's reply.
+const TEST_TIMEOUT: Duration = Duration::from_secs(3);
+/// Homebrew formula printed by `iosync --print-formula`, with `{version}` and
+/// `{sha256}` to fill in.
+const FORMULA_TEMPLATE: &str = r##"class SshClipboard < Formula
+  desc "Sync the clipboard between a headless Linux box and a macOS desktop over SSH"
+  homepage "https://github.com/tapthaker/ssh-clipboard"
+  url "https://github.com/tapthaker/ssh-clipboard/archive/refs/tags/v{version}.tar.gz"
+  sha256 "{sha256}"
+
+  depends_on "rust" => :build
+
+  def install
+    system "cargo", "build", "--release", "--locked"
+    bin.install "target/release/ssh-clipboard"
+  end
+
+  test do
+    assert_match version.to_s, shell_output("#{bin}/ssh-clipboard iosync --version")
+  end
+end
+"##;
+
+/// Settings read from the TOML config file. Every field is optional and named
+/// after the corresponding command-line flag, except the `[filters]` table.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct Config {
+    socket: Option<String>,
+    abstract_socket: Option<bool>,
+    socket_mode: Option<u32>,
+    tcp: Option<String>,
+    mmap: Option<PathBuf>,
+    metrics_socket: Option<String>,
+    pid_file: Option<PathBuf>,
+    state_file: Option<PathBuf>,
+    image_out: Option<PathBuf>,
+    audit_log: Option<PathBuf>,
+    log: Option<PathBuf>,
+    log_max_bytes: Option<u64>,
+    log_backups: Option<usize>,
+    log_level: Option<String>,
+    poll_ms: Option<u64>,
+    debounce_ms: Option<u64>,
+    history_size: Option<usize>,
+    clipboard_ttl_s: Option<u64>,
+    heartbeat_s: Option<u64>,
+    heartbeat_timeout_s: Option<u64>,
+    auth_key: Option<String>,
+    allow_uids: Option<Vec<u32>>,
+    retry_ms: Option<u64>,
+    retry_count: Option<u32>,
+    max_bytes: Option<usize>,
+    compress_threshold: Option<usize>,
+    prefix: Option<String>,
+    format: Option<String>,
+    normalize_newlines: Option<bool>,
+    trim: Option<bool>,
+    strip_tracking: Option<bool>,
+    conflict: Option<String>,
+    rate_limit: Option<u32>,
+    global_rate_limit: Option<u32>,
+    max_bandwidth_bps: Option<u64>,
+    shutdown_timeout_s: Option<u64>,
+    dbus: Option<bool>,
+    filters: Filters,
+}
+
+/// The `[filters]` table of the config file.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct Filters {
+    /// Regexes for content that must not be synced, like `--skip-pattern`.
+    skip: Vec<String>,
+    /// Regexes for content that is synced redacted, like `--mask-pattern`.
+    mask: Vec<String>,
+    /// Query parameters removed by `--strip-tracking`, replacing the defaults.
+    tracking_params: Option<Vec<String>>,
+}
+
+impl Config {
+    /// Load the file named by `--config`, or the default config file if it
+    /// exists. A missing default file yields an empty config.
+    fn load(path: Option<&Path>) -> Result<Config, String> {
+        let (path, explicit) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match default_config_path() {
+                Some(path) => (path, false),
+                None => return Ok(Config::default()),
+            },
+        };
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if !explicit && e.kind() == io::ErrorKind::NotFound => {
+                return Ok(Config::default())
             }
+            Err(e) => return Err(format!("cannot read {}: {}", path.display(), e)),
+        };
+        toml::from_str(&contents).map_err(|e| format!("invalid config {}: {}", path.display(), e))
+    }
+}
+
+/// Helper: `$XDG_CONFIG_HOME/ssh-clipboard/config.toml`, falling back to
+/// `~/.config/ssh-clipboard/config.toml`.
+fn default_config_path() -> Option<PathBuf> {
+    let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_dir.join("ssh-clipboard").join(CONFIG_FILE_NAME))
+}
+
+/// Helper: `<socket>.pid`, so servers on different sockets, such as one per
+/// SSH session, don't lock each other out. An abstract name gets its file at
+/// the path it spells.
+fn default_pid_path(socket_path: &str) -> PathBuf {
+    PathBuf::from(format!("{}.pid", socket_path.trim_start_matches('@')))
+}
+
+/// Helper: `<socket>.metrics`, for the same reason as `default_pid_path`.
+fn default_metrics_path(socket_path: &str) -> String {
+    format!("{}.metrics", socket_path.trim_start_matches('@'))
+}
+
+/// Helper: `$XDG_CACHE_HOME/ssh-clipboard/last.json`, falling back to
+/// `~/.cache/ssh-clipboard/last.json`.
+fn default_state_path() -> Option<PathBuf> {
+    let cache_dir = match env::var_os("XDG_CACHE_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+    };
+    Some(cache_dir.join("ssh-clipboard").join(STATE_FILE_NAME))
+}
+
+/// Helper: `$XDG_DATA_HOME/ssh-clipboard/audit.log`, falling back to
+/// `~/.local/share/ssh-clipboard/audit.log`.
+fn default_audit_log_path() -> Option<PathBuf> {
+    let data_dir = match env::var_os("XDG_DATA_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".local/share"),
+    };
+    Some(data_dir.join("ssh-clipboard").join(AUDIT_LOG_NAME))
+}
+
+/// Resolve the options. Flags and environment variables, both parsed by clap,
+/// take precedence over the config file, which takes precedence over the
+/// built-in defaults. Flags of the other mode are passed as defaults.
+fn parse_options(
+    shared: &SharedArgs,
+    server: &IosyncArgs,
+    client: &XclipArgs,
+) -> Result<Options, String> {
+    let config = Config::load(shared.config.as_deref())?;
+    // Inside an SSH session, each session gets its own socket so clipboards
+    // don't leak between sessions.
+    let socket_path = match shared.socket.clone().or(config.socket) {
+        Some(path) if path != SOCKET_AUTO => path,
+        _ => env::var("SSH_CONNECTION")
+            .ok()
+            .and_then(|connection| session_socket_path(&connection))
+            .unwrap_or_else(|| DEFAULT_SOCKET_PATH.to_string()),
+    };
+    let abstract_socket = shared.abstract_socket || config.abstract_socket.unwrap_or(false);
+    let socket_mode = server
+        .socket_mode
+        .or(config.socket_mode)
+        .unwrap_or(DEFAULT_SOCKET_MODE);
+    let tcp_addr = shared.tcp.clone().or(config.tcp);
+    // An empty path in the config file disables metrics like --no-metrics.
+    let metrics_socket = if server.no_metrics {
+        None
+    } else {
+        let path = server
+            .metrics_socket
+            .clone()
+            .or(config.metrics_socket)
+            .unwrap_or_else(|| match &tcp_addr {
+                Some(_) => DEFAULT_METRICS_SOCKET_PATH.to_string(),
+                None => default_metrics_path(&socket_path),
+            });
+        Some(path).filter(|path| !path.is_empty())
+    };
+    let pid_path = server
+        .pid_file
+        .clone()
+        .or(config.pid_file)
+        .unwrap_or_else(|| match &tcp_addr {
+            Some(_) => PathBuf::from(DEFAULT_PID_PATH),
+            None => default_pid_path(&socket_path),
+        });
+    let state_path = server
+        .state_file
+        .clone()
+        .or(config.state_file)
+        .or_else(default_state_path);
+    let image_out = server.image_out.clone().or(config.image_out);
+    let audit_log = if server.no_audit_log {
+        None
+    } else {
+        server
+            .audit_log
+            .clone()
+            .or(config.audit_log)
+            .or_else(default_audit_log_path)
+    };
+    let log_path = if shared.no_log {
+        None
+    } else {
+        let path = shared
+            .log
+            .clone()
+            .or(config.log)
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_LOG_PATH));
+        Some(path)
+    };
+    let log_max_bytes = shared
+        .log_max_bytes
+        .or(config.log_max_bytes)
+        .unwrap_or(DEFAULT_LOG_MAX_BYTES);
+    let log_backups = shared
+        .log_backups
+        .or(config.log_backups)
+        .unwrap_or(DEFAULT_LOG_BACKUPS);
+    let log_level = match shared.log_level.clone().or(config.log_level) {
+        Some(value) => {
+            Level::parse(&value).ok_or_else(|| format!("invalid log level: {}", value))?
         }
+        None => Level::Info,
+    };
+    let poll_ms = server.poll_ms.or(config.poll_ms).unwrap_or(DEFAULT_POLL_MS);
+    let poll_ms = check_poll_ms(poll_ms)?;
+    let debounce_ms = server
+        .debounce_ms
+        .or(config.debounce_ms)
+        .unwrap_or(DEFAULT_DEBOUNCE_MS);
+    let history_size = server
+        .history_size
+        .or(config.history_size)
+        .unwrap_or(DEFAULT_HISTORY_SIZE);
+    let clipboard_ttl = server
+        .clipboard_ttl_s
+        .or(config.clipboard_ttl_s)
+        .map(Duration::from_secs);
+    // Zero disables either side of the heartbeat.
+    let heartbeat_interval = server
+        .heartbeat_s
+        .or(config.heartbeat_s)
+        .unwrap_or(DEFAULT_HEARTBEAT_S);
+    let heartbeat_timeout = server
+        .heartbeat_timeout_s
+        .or(config.heartbeat_timeout_s)
+        .unwrap_or(DEFAULT_HEARTBEAT_TIMEOUT_S);
+    let auth_key = match shared.auth_key.clone().or(config.auth_key) {
+        Some(value) => Some(hex::decode(value.trim()).map_err(|_| "invalid auth key")?),
+        None => None,
+    };
+    let allow_uids = if server.allow_uid.is_empty() {
+        config.allow_uids.unwrap_or_default()
+    } else {
+        server.allow_uid.clone()
+    };
+    let retry_ms = client
+        .retry_ms
+        .or(config.retry_ms)
+        .unwrap_or(DEFAULT_RETRY_MS);
+    let retry_count = client.retry_count.or(config.retry_count).unwrap_or(0);
+    let max_bytes = server
+        .max_bytes
+        .or(config.max_bytes)
+        .unwrap_or(DEFAULT_MAX_BYTES);
+    let sync_prefix = server
+        .prefix
+        .clone()
+        .or(config.prefix)
+        .unwrap_or_else(|| DEFAULT_SYNC_PREFIX.to_string());
+    let wire_format = match server.format.clone().or(config.format) {
+        Some(value) => {
+            WireFormat::parse(&value).ok_or_else(|| format!("invalid format: {}", value))?
+        }
+        None => WireFormat::Json,
+    };
+    let compress_threshold = server
+        .compress_threshold
+        .or(config.compress_threshold)
+        .unwrap_or(DEFAULT_COMPRESS_THRESHOLD);
+    // Skip and mask patterns from the config file and the command line all
+    // apply, so a flag can't accidentally disable a filter set up in the config.
+    let skip_patterns = config
+        .filters
+        .skip
+        .iter()
+        .chain(&server.skip_pattern)
+        .map(|pattern| {
+            Regex::new(pattern).map_err(|e| format!("invalid skip pattern {}: {}", pattern, e))
+        })
+        .collect::<Result<_, _>>()?;
+    let mask_patterns = config
+        .filters
+        .mask
+        .iter()
+        .chain(&server.mask_pattern)
+        .map(|pattern| {
+            Regex::new(pattern).map_err(|e| format!("invalid mask pattern {}: {}", pattern, e))
+        })
+        .collect::<Result<_, _>>()?;
+    let conflict_strategy = match server.conflict.clone().or(config.conflict) {
+        Some(value) => ConflictStrategy::parse(&value)
+            .ok_or_else(|| format!("invalid conflict strategy: {}", value))?,
+        None => ConflictStrategy::LastWriterWins,
+    };
+    let normalize = NormOpts {
+        newlines: server.normalize_newlines || config.normalize_newlines.unwrap_or(false),
+        trim: server.trim || config.trim.unwrap_or(false),
+    };
+    let tracking_params = if server.strip_tracking || config.strip_tracking.unwrap_or(false) {
+        config.filters.tracking_params.unwrap_or_else(|| {
+            DEFAULT_TRACKING_PARAMS
+                .iter()
+                .map(|param| param.to_string())
+                .collect()
+        })
+    } else {
+        Vec::new()
+    };
+    Ok(Options {
+        socket_path,
+        abstract_socket,
+        socket_mode,
+        tcp_addr,
+        metrics_socket,
+        pid_path,
+        state_path,
+        image_out,
+        audit_log,
+        log_path,
+        log_max_bytes,
+        log_backups,
+        log_level,
+        poll_interval: Duration::from_millis(poll_ms),
+        debounce: Duration::from_millis(debounce_ms),
+        history_size,
+        clipboard_ttl,
+        heartbeat_interval: (heartbeat_interval > 0)
+            .then(|| Duration::from_secs(heartbeat_interval)),
+        heartbeat_timeout: (heartbeat_timeout > 0).then(|| Duration::from_secs(heartbeat_timeout)),
+        auth_key,
+        allow_uids,
+        retry_interval: Duration::from_millis(retry_ms),
+        retry_count,
+        verbose: client.verbose,
+        max_bytes,
+        compress_threshold,
+        sync_prefix,
+        wire_format,
+        skip_patterns,
+        mask_patterns,
+        normalize,
+        tracking_params,
+        conflict_strategy,
+        rate_limit: server
+            .rate_limit
+            .or(config.rate_limit)
+            .unwrap_or(DEFAULT_RATE_LIMIT),
+        global_rate_limit: server
+            .global_rate_limit
+            .or(config.global_rate_limit)
+            .unwrap_or(DEFAULT_GLOBAL_RATE_LIMIT),
+        max_bandwidth_bps: server
+            .max_bandwidth_bps
+            .or(config.max_bandwidth_bps)
+            .filter(|&rate| rate > 0),
+        shutdown_timeout: Duration::from_secs(
+            server
+                .shutdown_timeout_s
+                .or(config.shutdown_timeout_s)
+                .unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT_S),
+        ),
+        takeover: server.takeover,
+        mmap_path: shared.mmap.clone().or(config.mmap),
+        dbus: server.dbus || config.dbus.unwrap_or(false),
+    })
+}
+
+/// Helper: check that a polling interval is within `POLL_MS_RANGE`.
+fn check_poll_ms(poll_ms: u64) -> Result<u64, String> {
+    if !POLL_MS_RANGE.contains(&poll_ms) {
+        return Err(format!(
+            "poll interval must be between {} and {} ms, got {}",
+            POLL_MS_RANGE.start(),
+            POLL_MS_RANGE.end(),
+            poll_ms
+        ));
+    }
+    Ok(poll_ms)
+}
+
+/// Helper: print a message's content to stdout, as text when possible.
+fn print_message(msg: &Message) -> io::Result<()> {
+    match msg.as_text() {
+        Some(text) => println!("{}", text),
+        None => io::stdout().write_all(&msg.data)?,
+    }
+    Ok(())
+}
+
+/// Helper: read the `-selection` flag (or its `-sel` abbreviation). Like xclip,
+/// any prefix of "primary" or "clipboard" is accepted. Returns true for PRIMARY.
+fn parse_selection(selection: Option<&str>) -> io::Result<bool> {
+    let Some(selection) = selection else {
+        return Ok(false);
+    };
+    if !selection.is_empty() && "primary".starts_with(selection) {
+        Ok(true)
+    } else if !selection.is_empty() && "clipboard".starts_with(selection) {
+        Ok(false)
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unsupported selection: {}", selection),
+        ))
     }
-    return Ok(());
-}
-
-fn run_iosync_mode_on_mac(last_message: Arc<Mutex<String>>) -> io::Result<()> {
-
-    log!("Running on macOS");
-    // Thread that monitors the clipboard changes.
-    let last_message_for_clipboard = Arc::clone(&last_message);
-    let clipboard_thread = thread::spawn(move || {
-        match Clipboard::new() {
-            Ok(mut clipboard) => {
-                loop {
-                    thread::sleep(Duration::from_millis(200));
-                    if let Ok(text) = clipboard.get_text() {
-                        let mut last = last_message_for_clipboard.lock().unwrap();
-                        if *last != text {
-                            log!("Clipboard changed: {}", text);
-                            let msg = Message {
-                                content: text.clone(),
-                            };
-                            if let Ok(msg_str) = serde_json::to_string(&msg) {
-                                *last = text.clone();
-                                eprintln!("CLIPBOARD-SYNC:{}", msg_str);
-                                log!("CLIPBOARD-SYNC:{}", msg_str);
-                            }
-                        }
-                    }
+}
+
+/// The xclip mode: connect over the same transport the server listens on.
+fn run_xclip_mode(args: &XclipArgs, opts: &Options) -> Result<(), Error> {
+    match &opts.tcp_addr {
+        Some(addr) => run_xclip_client(ClipboardClient::new(TcpTransport::new(addr), opts), args),
+        None => run_xclip_client(ClipboardClient::new(UnixTransport::new(opts), opts), args),
+    }
+}
+
+/// The xclip client: either reads (with "-o") or writes to the server.
+/// `-t <mime>` transfers raw bytes of the given content type instead of text,
+/// `-selection primary` uses the PRIMARY selection, `--history <n>` prints
+/// the nth most recent synced entry, `--slot <name>` reads or writes a named
+/// slot instead of the clipboard, `--list-slots` lists them, `--clear` empties
+/// the clipboard, `--wipe` the history, selections and slots as well, and
+/// `--ping` prints the server's round-trip latency (`--test` as well, with a
+/// timeout), and like xclip,
+/// `-o -t TARGETS` lists the content types the server supports. With `-o`, `--wait <ms>`
+/// waits up to that long for the clipboard to change and prints the new content.
+/// `--tmux` takes text from tmux's paste buffer instead of stdin, and with `-o`
+/// loads it there instead of printing it. `--neovim-compat` adapts all of this
+/// to neovim's clipboard provider; see `docs/neovim.md`. `--dry-run` prints the
+/// command instead of changing the clipboard.
+fn run_xclip_client<T: Transport>(
+    client: ClipboardClient<T>,
+    args: &XclipArgs,
+) -> Result<(), Error> {
+    let primary = parse_selection(args.selection.as_deref())?;
+    if args.test {
+        let rtt = client
+            .with_timeout(TEST_TIMEOUT)
+            .ping()
+            .map_err(|e| match e {
+                Error::Io(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("the server did not answer within {:?}", TEST_TIMEOUT),
+                    )
+                    .into()
                 }
+                e => e,
+            })?;
+        println!("{:.3} ms", rtt.as_secs_f64() * 1000.0);
+    } else if args.ping {
+        let rtt = client.ping()?;
+        println!("{:.3} ms", rtt.as_secs_f64() * 1000.0);
+    } else if args.clear {
+        if args.dry_run {
+            print_dry_run("DELETE");
+        } else {
+            client.clear()?;
+        }
+    } else if args.wipe {
+        if args.dry_run {
+            print_dry_run("WIPE");
+        } else {
+            client.wipe()?;
+        }
+    } else if let Some(index) = args.history {
+        // History mode: print the entry's content.
+        print_message(&client.history(index)?)?;
+    } else if let Some(query) = &args.search {
+        // Search mode: one line per match, newest first.
+        if args.dry_run {
+            let command = if args.regex { "SEARCH_REGEX" } else { "SEARCH" };
+            print_dry_run(&format!("{} {}", command, query));
+        }
+        for found in client.search(query, args.regex)? {
+            let recorded = UNIX_EPOCH + Duration::from_millis(found.message.sent_at_ms);
+            println!(
+                "{}\t{}\t{}",
+                found.index,
+                humantime::format_rfc3339_seconds(recorded),
+                peek_line(&found.message, DEFAULT_PEEK_CHARS)
+            );
+        }
+    } else if let Some(path) = &args.export {
+        // Export mode: save the history, newest first.
+        if args.dry_run {
+            print_dry_run("HISTORY ALL");
+        }
+        let entries = client.history_all()?;
+        // The history is the clipboard in cleartext, so only we may read it.
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)?;
+        let mut writer = io::BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, &entries)?;
+        writer.flush()?;
+    } else if let Some(path) = &args.import {
+        // Import mode: add the entries oldest first, so they keep their order.
+        let entries: Vec<Message> = serde_json::from_slice(&std::fs::read(path)?)?;
+        for msg in entries.iter().rev() {
+            if args.dry_run {
+                print_dry_run(&format!("IMPORT_HISTORY <{} bytes>", msg.data.len()));
+            } else {
+                client.import_history(msg)?;
             }
-            Err(e) => {
-                log!("Failed to open clipboard: {}", e);
+        }
+    } else if args.list_slots {
+        // Slots mode: print each selection and slot with its size.
+        for (name, size) in client.list()? {
+            println!("{}\t{}", name, size);
+        }
+    } else if let Some(name) = &args.slot {
+        if args.output {
+            println!("{}", client.get_slot(name)?);
+        } else {
+            let stdin = io::stdin();
+            let lines: Vec<_> = stdin.lock().lines().map_while(Result::ok).collect();
+            let input = lines.join("\n");
+            if args.dry_run {
+                print_dry_run(&format!("SET_SLOT {} {}", name, input));
+            } else {
+                client.set_slot(name, &input)?;
             }
         }
-    });
-
-    let last_message_for_stdin = Arc::clone(&last_message);
-    let stdin_thread = thread::spawn(move || {
-        let stdin = io::stdin();
-        for line in stdin.lock().lines() {
-            if let Ok(line) = line {
-                log!("Received stdin: {}", line);
-                // Check if the line starts with "CLIPBOARD_SYNC:".
-                if line.starts_with("CLIPBOARD_SYNC:") {
-                    // Extract the message after the command.
-                    let msg_str = line["CLIPBOARD_SYNC:".len()..].trim().to_string();
-                    if let Ok(msg) = serde_json::from_str::<Message>(&msg_str) {
-                        let mut last = last_message_for_stdin.lock().unwrap();
-                        if *last != msg.content {
-                            log!("Setting clipboard to: {}", msg.content);
-                            *last = msg.content.clone();
-                            let mut clipboard = Clipboard::new().expect("Failed to open clipboard");
-                            let _ = clipboard.set_text(msg.content);
-                        }
-                    }
-                } else {
-                    println!("{}", line);
+    } else if args.target.as_deref() == Some(TARGETS) && args.output {
+        // Targets mode: print one supported type per line.
+        for content_type in client.capabilities()? {
+            println!("{}", content_type);
+        }
+    } else if let Some(content_type) = args.target.as_deref().filter(|_| !primary) {
+        if args.output {
+            // Read mode: write the raw bytes of the first type in the list
+            // that the content is available in.
+            let types: Vec<_> = parse_types(content_type).collect();
+            if args.dry_run {
+                print_dry_run(&format!("GET_FORMAT {}", types.join(",")));
+            }
+            if let Some((_, data)) = client.get_format(&types)? {
+                io::stdout().write_all(&data)?;
+            }
+        } else {
+            // Write mode: send stdin as raw bytes.
+            let mut input = Vec::new();
+            io::stdin().read_to_end(&mut input)?;
+            if args.dry_run {
+                print_dry_run(&format!(
+                    "SET_DATA {} <{} bytes>",
+                    content_type,
+                    input.len()
+                ));
+            } else {
+                client.set_data(content_type, &input)?;
+            }
+        }
+    } else if args.diff {
+        // Diff mode: show how the next change differs from the current content.
+        let before = client.get()?;
+        let after = client.wait(DIFF_WAIT)?;
+        let diff = TextDiff::from_lines(&before, &after);
+        print!("{}", diff.unified_diff().header("before", "after"));
+    } else if args.stats {
+        // Stats mode: summarize STATUS.
+        if args.dry_run {
+            print_dry_run("STATUS");
+        }
+        println!("{}", stats_line(&client.status()?));
+    } else if args.qr {
+        // QR mode: draw the text for a phone's camera.
+        if args.dry_run {
+            print_dry_run("GET");
+        }
+        println!("{}", render_qr(&client.get()?)?);
+    } else if args.peek {
+        // Peek mode: one line describing the clipboard.
+        if args.dry_run {
+            print_dry_run("GET_MESSAGE");
+        }
+        let max_chars = args.max_chars.unwrap_or(DEFAULT_PEEK_CHARS);
+        println!("{}", peek_line(&client.get_message()?, max_chars));
+    } else if args.format.as_deref() == Some("json") {
+        // JSON mode: print the message with its metadata, one line.
+        if primary {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "--format json only reads the clipboard",
+            )
+            .into());
+        }
+        if args.dry_run {
+            print_dry_run("GET_MESSAGE");
+        }
+        println!("{}", serde_json::to_string(&client.get_message()?)?);
+    } else if let Some(timeout) = args.wait.filter(|_| !primary) {
+        // Wait mode: print the content once it changes.
+        println!("{}", client.wait(Duration::from_millis(timeout))?);
+    } else if args.output {
+        // Read mode: print the content, or load it into tmux. Reading is
+        // harmless, so --dry-run goes ahead with it.
+        if args.dry_run {
+            print_dry_run(if primary { "GET_PRIMARY" } else { "GET" });
+        }
+        let text = if primary {
+            match client.get_primary() {
+                Err(e) if args.neovim_compat => {
+                    log!(DEBUG, "PRIMARY unavailable, treating it as empty: {}", e);
+                    String::new()
                 }
+                text => text?,
             }
+        } else {
+            client.get()?
+        };
+        if args.tmux {
+            tmux_load_buffer(&text)?;
+        } else if args.neovim_compat {
+            // Like xclip, don't add a newline neovim would paste.
+            print!("{}", text);
+        } else {
+            println!("{}", text);
+        }
+    } else {
+        // Write mode: read from stdin or tmux, then send it.
+        let input = if args.tmux {
+            tmux_save_buffer()?
+        } else if args.neovim_compat {
+            // Keep the trailing newline: it is how neovim marks linewise yanks.
+            let mut input = String::new();
+            io::stdin().read_to_string(&mut input)?;
+            input
+        } else {
+            let stdin = io::stdin();
+            let lines: Vec<_> = stdin.lock().lines().map_while(Result::ok).collect();
+            lines.join("\n")
+        };
+        if args.dry_run {
+            let command = if primary { "SET_PRIMARY" } else { "SET" };
+            print_dry_run(&format!("{} {}", command, input));
+        } else if primary {
+            match client.set_primary(&input) {
+                Err(e) if args.neovim_compat => {
+                    log!(DEBUG, "PRIMARY unavailable, not setting it: {}", e);
+                }
+                result => result?,
+            }
+        } else {
+            client.set(&input)?;
+        }
+    }
+    Ok(())
+}
+
+/// Helper: the `--stats` line, e.g. `Uptime: 3h 42m | Syncs: 1,234 |
+/// Sent: 4.2 MiB | Received: 1.1 MiB | Last sync: 3s ago`.
+fn stats_line(status: &Status) -> String {
+    let last_sync = match status.last_sync_ms {
+        Some(ms) => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            let ago = now.as_millis().saturating_sub(u128::from(ms)) / 1000;
+            format!("{} ago", short_duration(ago as u64))
         }
-    });
+        None => "never".to_string(),
+    };
+    format!(
+        "Uptime: {} | Syncs: {} | Sent: {} | Received: {} | Last sync: {}",
+        short_duration(status.uptime_s),
+        thousands(status.messages_synced),
+        binary_size(status.bytes_sent),
+        binary_size(status.bytes_received),
+        last_sync
+    )
+}
 
-    clipboard_thread.join().expect("Clipboard thread panicked");
-    stdin_thread.join().expect("Stdin thread panicked");
+/// Helper: a duration in its two largest units, e.g. `3h 42m` or `12s`.
+fn short_duration(secs: u64) -> String {
+    match secs {
+        0..60 => format!("{}s", secs),
+        60..3600 => format!("{}m {}s", secs / 60, secs % 60),
+        3600..86400 => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+        _ => format!("{}d {}h", secs / 86400, secs % 86400 / 3600),
+    }
+}
 
-    return Ok(());
+/// Helper: a count with thousands separators, e.g. `1,234`.
+fn thousands(n: u64) -> String {
+    let digits = n.to_string();
+    let mut grouped = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped
 }
 
-/// The iosync mode: run a server on a Unix domain socket and monitor the clipboard.
-fn run_iosync_mode() -> io::Result<()> {
-    // Shared state for the most recent clipboard message.
-    let last_message = Arc::new(Mutex::new(String::new()));
-    if cfg!(target_os = "linux") {
-        // Listen on the Unix domain socket if we are running inside
-        // a Linux box
-        // The assumption is that you are sshing into a Linux box that doesn't have a GUI
-        // Thus we are using the xclip mode to notify this server of clipboard changes
-        return run_iosync_mode_on_linux(last_message);
-    } else {
-        // Listen to macOS clipboard changes
-        return run_iosync_mode_on_mac(last_message);
+/// Helper: a size in bytes with a binary unit, e.g. `4.2 MiB`.
+fn binary_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
     }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
 }
 
-/// The xclip mode: act as a client that either reads (with "-o") or writes to the socket.
-fn run_xclip_mode() -> io::Result<()> {
-    let args: Vec<String> = env::args().collect();
-    // Connect to the Unix domain socket.
-    match UnixStream::connect(SOCKET_PATH) {
-        Ok(mut stream) => {
-            if args.len() > 1 && args[1] == "-o" {
-                // Read mode: send "GET" and print the reply.
-                stream.write_all(b"GET\n")?;
-                let mut reply = String::new();
-                stream.read_to_string(&mut reply)?;
-                println!("{}", reply);
+/// Helper: `text` as a QR code drawn with half-block characters, two modules
+/// per character. Light and dark are swapped for the light-on-dark text of most
+/// terminals, which phone cameras read all the same.
+fn render_qr(text: &str) -> io::Result<String> {
+    let code = QrCode::with_error_correction_level(text, EcLevel::L).map_err(|e| match e {
+        QrError::DataTooLong => io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "the clipboard holds {} bytes, more than a QR code holds (up to {} bytes)",
+                text.len(),
+                QR_MAX_BYTES
+            ),
+        ),
+        e => io::Error::new(io::ErrorKind::InvalidInput, e.to_string()),
+    })?;
+    Ok(code
+        .render::<Dense1x2>()
+        .dark_color(Dense1x2::Light)
+        .light_color(Dense1x2::Dark)
+        .build())
+}
+
+/// Helper: the `--peek` line: up to `max_chars` characters of text, with
+/// control characters such as newlines escaped, then the size and type.
+fn peek_line(msg: &Message, max_chars: usize) -> String {
+    let mut line = String::new();
+    if let Some(text) = msg.as_text() {
+        for c in text.chars().take(max_chars) {
+            if c.is_control() {
+                line.extend(c.escape_debug());
             } else {
-                // Write mode: read from stdin, then send "SET <input>".
-                let stdin = io::stdin();
-                let input: String = stdin
-                    .lock()
-                    .lines()
-                    .filter_map(Result::ok)
-                    .collect::<Vec<_>>()
-                    .join("\n");
-                let cmd = format!("SET {}", input);
-                stream.write_all(cmd.as_bytes())?;
-                let mut reply = String::new();
-                stream.read_to_string(&mut reply)?;
+                line.push(c);
             }
-            Ok(())
         }
-        Err(e) => {
-            log!("Failed to connect to the iosync socket: {}", e);
-            return Err(e);
+        if text.chars().nth(max_chars).is_some() {
+            line.push('\u{2026}');
         }
+        line.push(' ');
     }
+    line + &format!("({} bytes, {})", msg.data.len(), msg.content_type)
 }
 
-fn main() {
-    // Decide mode based on the executable name.
-    let exe_name = env::args().next().unwrap_or_default();
-    if exe_name.ends_with("xclip") {
-        log!("Running in xclip mode");
-        if let Err(err) = run_xclip_mode() {
-            log!("Error in xclip mode: {}", err);
-            std::process::exit(1);
+/// Helper: print the command `--dry-run` shows in place of sending it.
+fn print_dry_run(command: &str) {
+    eprintln!("[dry-run] would send: {}", command);
+}
+
+/// Helper: the contents of tmux's most recent paste buffer.
+fn tmux_save_buffer() -> io::Result<String> {
+    let output = process::Command::new("tmux")
+        .args(["save-buffer", "-"])
+        .stderr(process::Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "tmux save-buffer failed: {}",
+            output.status
+        )));
+    }
+    String::from_utf8(output.stdout).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Helper: replace tmux's paste buffer with `text`.
+fn tmux_load_buffer(text: &str) -> io::Result<()> {
+    let mut child = process::Command::new("tmux")
+        .args(["load-buffer", "-"])
+        .stdin(process::Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(text.as_bytes())?;
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "tmux load-buffer failed: {}",
+            status
+        )));
+    }
+    Ok(())
+}
+
+/// The result of one `--diagnose` check: what was found, or the problem and
+/// how to fix it.
+type Check = Result<String, (String, &'static str)>;
+
+/// Run the `--diagnose` checklist, printing each result. True if all passed.
+fn diagnose(opts: &Options) -> bool {
+    let server = match &opts.tcp_addr {
+        Some(addr) => check_server(TcpTransport::new(addr), opts),
+        None => check_server(UnixTransport::new(opts), opts),
+    };
+    let checks = [
+        check_bind(opts),
+        check_log(opts),
+        check_clipboard(),
+        check_ssh_session(),
+        server,
+    ];
+    let mut passed = true;
+    for check in checks {
+        match check {
+            Ok(found) => println!("\u{2713} {}", found),
+            Err((problem, hint)) => {
+                println!("\u{2717} {}\n    {}", problem, hint);
+                passed = false;
+            }
+        }
+    }
+    passed
+}
+
+/// Helper: whether a socket could be created where the server listens.
+fn check_bind(opts: &Options) -> Check {
+    if let Some(addr) = &opts.tcp_addr {
+        if TcpTransport::new(addr).connect().is_ok() {
+            return Ok(format!("a server holds TCP address {}", addr));
+        }
+        return match std::net::TcpListener::bind(addr) {
+            Ok(_) => Ok(format!("can listen on TCP address {}", addr)),
+            Err(e) => Err((
+                format!("cannot listen on TCP address {}: {}", addr, e),
+                "Pick a free address with --tcp HOST:PORT.",
+            )),
+        };
+    }
+    if opts.abstract_socket || opts.socket_path.starts_with('@') {
+        return Ok("abstract sockets need no file".to_string());
+    }
+    // Bind a socket next to the real one, which a server may hold.
+    let probe = format!("{}.diagnose", opts.socket_path);
+    match std::os::unix::net::UnixListener::bind(&probe) {
+        Ok(_) => {
+            let _ = std::fs::remove_file(&probe);
+            Ok(format!("can create the socket {}", opts.socket_path))
+        }
+        Err(e) => Err((
+            format!("cannot create the socket {}: {}", opts.socket_path, e),
+            "Pick a writable directory with --socket PATH.",
+        )),
+    }
+}
+
+/// Helper: whether the log file can be opened for appending.
+fn check_log(opts: &Options) -> Check {
+    let Some(path) = &opts.log_path else {
+        return Ok("logging is disabled".to_string());
+    };
+    match std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+    {
+        Ok(_) => Ok(format!("can write the log file {}", path.display())),
+        Err(e) => Err((
+            format!("cannot write the log file {}: {}", path.display(), e),
+            "Pick another file with --log PATH, or disable logging with --no-log.",
+        )),
+    }
+}
+
+/// Helper: whether the system clipboard can be opened.
+fn check_clipboard() -> Check {
+    match arboard::Clipboard::new() {
+        Ok(_) => Ok("the system clipboard is accessible".to_string()),
+        Err(e) => Err((
+            format!("cannot open the system clipboard: {}", e),
+            "Without a display (DISPLAY or WAYLAND_DISPLAY), only socket clients share the clipboard.",
+        )),
+    }
+}
+
+/// Helper: whether we run inside an SSH session, which picks the socket.
+fn check_ssh_session() -> Check {
+    match env::var("SSH_CONNECTION") {
+        Ok(connection) => Ok(format!(
+            "in an SSH session from {}",
+            connection.split_whitespace().next().unwrap_or("?")
+        )),
+        Err(_) => Err((
+            "SSH_CONNECTION is not set, so every session shares one socket".to_string(),
+            "Run iosync in the SSH session, e.g. `ssh HOST ssh-clipboard iosync`, or pass --socket.",
+        )),
+    }
+}
+
+/// Helper: whether a server answers on the socket.
+fn check_server<T: Transport>(transport: T, opts: &Options) -> Check {
+    let name = transport.to_string();
+    let client = ClipboardClient::with_auth_key(transport, opts.auth_key.clone());
+    match client.ping() {
+        Ok(rtt) => Ok(format!(
+            "a server answers on the {} in {:.3} ms",
+            name,
+            rtt.as_secs_f64() * 1000.0
+        )),
+        Err(Error::AuthFailure) => Err((
+            format!("the server on the {} rejected our auth key", name),
+            "Use the server's --auth-key.",
+        )),
+        Err(_) if opts.tcp_addr.is_none() && Path::new(&opts.socket_path).exists() => Err((
+            format!("{} is a stale socket file", opts.socket_path),
+            "Remove it, or start iosync, which replaces it.",
+        )),
+        Err(e) => Err((
+            format!("no server answers on the {}: {}", name, e),
+            "Start iosync, with the same --socket as the clients.",
+        )),
+    }
+}
+
+/// The Homebrew formula of this version, with the SHA-256 of `tarball` if
+/// given.
+fn formula(tarball: Option<&Path>) -> io::Result<String> {
+    let sha256 = match tarball {
+        Some(path) => {
+            let tarball = std::fs::read(path).map_err(|e| {
+                io::Error::new(e.kind(), format!("cannot read {}: {}", path.display(), e))
+            })?;
+            hex::encode(Sha256::digest(tarball))
         }
+        None => "REPLACE_WITH_SHA256_OF_THE_TARBALL".to_string(),
+    };
+    Ok(FORMULA_TEMPLATE
+        .replace("{version}", env!("CARGO_PKG_VERSION"))
+        .replace("{sha256}", &sha256))
+}
+
+/// Helper: `~/Library`, which holds the user's launchd agents and logs.
+fn user_library() -> io::Result<PathBuf> {
+    let home = env::var_os("HOME").ok_or_else(|| io::Error::other("HOME is not set"))?;
+    Ok(PathBuf::from(home).join("Library"))
+}
+
+/// Helper: `~/Library/LaunchAgents/<label>.plist`, where launchd looks for
+/// the user's agents.
+fn launchd_plist_path() -> io::Result<PathBuf> {
+    let file_name = format!("{}.plist", LAUNCHD_LABEL);
+    Ok(user_library()?.join("LaunchAgents").join(file_name))
+}
+
+/// Helper: fail with a clear message anywhere but on macOS.
+fn require_launchd() -> io::Result<()> {
+    if cfg!(target_os = "macos") {
+        Ok(())
     } else {
-        log!("Running in iosync mode");
-        if let Err(err) = run_iosync_mode() {
-            log!("Error in iosync mode: {}", err);
-            std::process::exit(1);
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "launchd agents are only supported on macOS",
+        ))
+    }
+}
+
+/// Write the agent's plist, running `iosync` with `flags` (those given here
+/// but `--install-launchd`), and load it, replacing an agent installed before.
+/// Its output goes to `~/Library/Logs`.
+fn install_launchd(flags: &[String]) -> io::Result<()> {
+    require_launchd()?;
+    let path = launchd_plist_path()?;
+    let logs = user_library()?.join("Logs");
+    // The binary itself, not a link named after one mode.
+    let exe = env::current_exe()?.canonicalize()?;
+    let mut program_arguments = vec![exe.display().to_string(), "iosync".to_string()];
+    program_arguments.extend(flags.iter().cloned());
+    let plist = launchd_plist(
+        &program_arguments,
+        &logs.join("ssh-clipboard.out.log"),
+        &logs.join("ssh-clipboard.err.log"),
+    );
+    if path.exists() {
+        // Not loaded if the user unloaded it by hand; that's fine.
+        let _ = launchctl("unload", &path);
+    }
+    std::fs::create_dir_all(path.parent().expect("plist path has a parent"))?;
+    std::fs::create_dir_all(&logs)?;
+    std::fs::write(&path, plist)?;
+    launchctl("load", &path)?;
+    eprintln!("Installed and loaded {}", path.display());
+    Ok(())
+}
+
+/// Unload the agent `install_launchd` installed and remove its plist.
+fn uninstall_launchd() -> io::Result<()> {
+    require_launchd()?;
+    let path = launchd_plist_path()?;
+    if !path.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} is not installed", path.display()),
+        ));
+    }
+    launchctl("unload", &path)?;
+    std::fs::remove_file(&path)?;
+    eprintln!("Unloaded and removed {}", path.display());
+    Ok(())
+}
+
+/// Helper: run `launchctl <subcommand> <plist>`.
+fn launchctl(subcommand: &str, plist: &Path) -> io::Result<()> {
+    let status = process::Command::new("launchctl")
+        .arg(subcommand)
+        .arg(plist)
+        .status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "launchctl {} failed: {}",
+            subcommand, status
+        )));
+    }
+    Ok(())
+}
+
+/// Helper: parse the command line with clap, adding `--version`, which can't be
+/// declared in `cli` because the build script compiles it too. Exits on errors
+/// and after printing help or the version, like `Parser::parse`.
+fn parse_cli<P: CommandFactory + FromArgMatches>(args: impl IntoIterator<Item = String>) -> P {
+    // Print "ssh-clipboard <version>" however we were invoked.
+    let mut command = P::command()
+        .version(VERSION)
+        .display_name("ssh-clipboard")
+        .mut_subcommands(|subcommand| subcommand.version(VERSION).display_name("ssh-clipboard"));
+    let result = command
+        .try_get_matches_from_mut(args)
+        .and_then(|matches| P::from_arg_matches(&matches));
+    match result {
+        Ok(parsed) => parsed,
+        Err(err) => err.format(&mut command).exit(),
+    }
+}
+
+/// Helper: accept xclip's single-dash long flags, such as `-selection` and
+/// the `-quiet` that neovim passes.
+fn xclip_args(args: Vec<String>) -> impl Iterator<Item = String> {
+    args.into_iter().map(|arg| match arg.as_str() {
+        "-selection" | "-sel" | "-quiet" | "-silent" => format!("-{}", arg),
+        "-in" => "-i".to_string(),
+        "-out" => "-o".to_string(),
+        _ => arg,
+    })
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    // Decide mode based on the executable name, so a link named xclip or
+    // iosync takes that mode's flags; otherwise the subcommand decides.
+    let exe_name = args.first().cloned().unwrap_or_default();
+    let (cli, flags) = if exe_name.ends_with("xclip") {
+        let cli = Cli::Xclip(parse_cli::<Xclip>(xclip_args(args.clone())).args);
+        (cli, &args[1..])
+    } else if exe_name.ends_with("iosync") {
+        (
+            Cli::Iosync(parse_cli::<Iosync>(args.clone()).args),
+            &args[1..],
+        )
+    } else {
+        (parse_cli::<Cli>(args.clone()), &args[2..])
+    };
+    let opts = match &cli {
+        Cli::Iosync(args) => parse_options(&args.shared, args, &XclipArgs::default()),
+        Cli::Xclip(args) => parse_options(&args.shared, &IosyncArgs::default(), args),
+    };
+    let opts = match opts {
+        Ok(opts) => opts,
+        Err(err) => {
+            eprintln!("ssh-clipboard: {}", err);
+            std::process::exit(2);
+        }
+    };
+    // Before opening the log, which may be one of the problems.
+    if let Cli::Iosync(IosyncArgs { diagnose: true, .. }) = &cli {
+        std::process::exit(if diagnose(&opts) { 0 } else { 1 });
+    }
+    init_log(
+        opts.log_path.as_deref(),
+        opts.log_max_bytes,
+        opts.log_backups,
+        opts.log_level,
+    );
+    match cli {
+        Cli::Xclip(args) => {
+            log!(DEBUG, "Running in xclip mode");
+            if let Err(err) = run_xclip_mode(&args, &opts) {
+                log!(ERROR, "Error in xclip mode: {}", err);
+                eprintln!("xclip: {}", err);
+                // Neovim and health checks only tell success from failure,
+                // like real xclip.
+                std::process::exit(if args.neovim_compat || args.test {
+                    1
+                } else {
+                    err.exit_code()
+                });
+            }
+        }
+        Cli::Iosync(IosyncArgs {
+            print_formula: Some(tarball),
+            ..
+        }) => match formula(tarball.as_deref()) {
+            Ok(formula) => print!("{}", formula),
+            Err(err) => {
+                eprintln!("iosync: {}", err);
+                std::process::exit(1);
+            }
+        },
+        Cli::Iosync(iosync) if iosync.install_launchd || iosync.uninstall_launchd => {
+            let result = if iosync.install_launchd {
+                let flags: Vec<String> = flags
+                    .iter()
+                    .filter(|flag| *flag != "--install-launchd")
+                    .cloned()
+                    .collect();
+                install_launchd(&flags)
+            } else {
+                uninstall_launchd()
+            };
+            if let Err(err) = result {
+                eprintln!("iosync: {}", err);
+                std::process::exit(1);
+            }
+        }
+        Cli::Iosync(args) => {
+            log!(INFO, "Running in iosync mode");
+            // SIGHUP re-reads the config file; the flags still take precedence.
+            let server = ClipboardServer::new(opts)
+                .with_reload(move || parse_options(&args.shared, &args, &XclipArgs::default()));
+            if let Err(err) = server.run() {
+                log!(ERROR, "Error in iosync mode: {}", err);
+                eprintln!("iosync: {}", err);
+                std::process::exit(err.exit_code());
+            }
         }
     }
 }
@@ -0,0 +1,132 @@
+//! Shared-memory fast path for reading the clipboard on the same machine. The
+//! server mirrors the clipboard text into a memory-mapped file, which clients
+//! map and copy from without a round trip through the socket.
+//!
+//! The file starts with a header of two native-endian `u64`s: a sequence
+//! number, odd while the server is writing, and the length of the text that
+//! follows, or `UNAVAILABLE` when the clipboard holds something other than
+//! text. Readers copy the text and retry until the sequence number is the same
+//! even value before and after, so they never block the server.
+
+use memmap2::{Mmap, MmapMut};
+use std::fs::OpenOptions;
+use std::io;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{fence, AtomicU64, Ordering};
+use std::thread;
+
+/// Bytes before the text: the sequence number and the length.
+const HEADER_LEN: usize = 16;
+/// The length recorded when readers should ask the socket instead.
+const UNAVAILABLE: u64 = u64::MAX;
+/// How many times a reader retries a copy the server was writing over.
+const READ_ATTEMPTS: usize = 1000;
+
+/// The server's side of the mapping. The file is removed when it is dropped,
+/// unless another server has replaced it since.
+pub struct MappedClipboard {
+    map: MmapMut,
+    path: PathBuf,
+    inode: u64,
+}
+
+impl MappedClipboard {
+    /// Create the file at `path` with room for `capacity` bytes of text,
+    /// replacing any file left there, readable only as `mode` allows.
+    pub fn create(path: &Path, capacity: usize, mode: u32) -> io::Result<MappedClipboard> {
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .mode(mode)
+            .open(path)?;
+        file.set_len((HEADER_LEN + capacity) as u64)?;
+        let map = unsafe { MmapMut::map_mut(&file)? };
+        let mut mapped = MappedClipboard {
+            map,
+            path: path.to_path_buf(),
+            inode: file.metadata()?.ino(),
+        };
+        mapped.write(None);
+        Ok(mapped)
+    }
+
+    /// Publish `text`; `None`, or text that doesn't fit, sends readers to the
+    /// socket.
+    pub fn write(&mut self, text: Option<&str>) {
+        let text = text.filter(|text| text.len() <= self.map.len() - HEADER_LEN);
+        let start = header(&self.map).0.load(Ordering::Relaxed) | 1;
+        header(&self.map).0.store(start, Ordering::Relaxed);
+        fence(Ordering::Release);
+        let len = text.map_or(UNAVAILABLE, |text| text.len() as u64);
+        header(&self.map).1.store(len, Ordering::Relaxed);
+        if let Some(text) = text {
+            self.map[HEADER_LEN..HEADER_LEN + text.len()].copy_from_slice(text.as_bytes());
+        }
+        header(&self.map).0.store(start + 1, Ordering::Release);
+    }
+}
+
+impl Drop for MappedClipboard {
+    fn drop(&mut self) {
+        if std::fs::metadata(&self.path).is_ok_and(|metadata| metadata.ino() == self.inode) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Read the text the server mirrors into the file at `path`. `Ok(None)` means
+/// the clipboard holds something else, which only the socket serves.
+pub fn read_mapped(path: &Path) -> io::Result<Option<String>> {
+    let file = std::fs::File::open(path)?;
+    let map = unsafe { Mmap::map(&file)? };
+    if map.len() < HEADER_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "mapped clipboard too short",
+        ));
+    }
+    let (seq, len) = header(&map);
+    for _ in 0..READ_ATTEMPTS {
+        let before = seq.load(Ordering::Acquire);
+        if before % 2 == 1 {
+            thread::yield_now();
+            continue;
+        }
+        let text = match len.load(Ordering::Relaxed) {
+            UNAVAILABLE => None,
+            n => match usize::try_from(n) {
+                Ok(n) if n <= map.len() - HEADER_LEN => {
+                    Some(map[HEADER_LEN..HEADER_LEN + n].to_vec())
+                }
+                // A torn header; the sequence check below catches it.
+                _ => Some(Vec::new()),
+            },
+        };
+        fence(Ordering::Acquire);
+        if seq.load(Ordering::Relaxed) == before {
+            return match text {
+                Some(text) => String::from_utf8(text)
+                    .map(Some)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+                None => Ok(None),
+            };
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::WouldBlock,
+        "mapped clipboard kept changing",
+    ))
+}
+
+/// Helper: the sequence number and length at the start of a mapping, which is
+/// page-aligned.
+fn header(map: &[u8]) -> (&AtomicU64, &AtomicU64) {
+    let words = map.as_ptr() as *const AtomicU64;
+    unsafe { (&*words, &*words.add(1)) }
+}
@@ -0,0 +1,41 @@
+/*
+ * Smoke test for the C API. Build the library first, then:
+ *
+ *   cc tests/ffi_smoke.c -Itarget/debug -Ltarget/debug -lssh_clipboard -o /tmp/ffi_smoke
+ *   LD_LIBRARY_PATH=target/debug /tmp/ffi_smoke [socket]
+ *
+ * Without a socket argument it talks to a socket that doesn't exist and checks
+ * that both calls fail cleanly. With one, it round-trips a string through the
+ * running server.
+ */
+#include <stdio.h>
+#include <string.h>
+
+#include "ssh_clipboard.h"
+
+int main(int argc, char **argv) {
+    const char *socket = argc > 1 ? argv[1] : "/tmp/ssh_clipboard_ffi_missing.sock";
+    SshClipboardClient *client = ssh_clipboard_client_new(socket, NULL);
+    if (client == NULL) {
+        fprintf(stderr, "ssh_clipboard_client_new failed\n");
+        return 1;
+    }
+
+    int status = ssh_clipboard_set(client, "hello from C");
+    char *text = ssh_clipboard_get(client);
+    int ok;
+    if (argc > 1) {
+        ok = status == 0 && text != NULL && strcmp(text, "hello from C") == 0;
+    } else {
+        ok = status == 4 && text == NULL;
+    }
+
+    ssh_clipboard_string_free(text);
+    ssh_clipboard_free(client);
+    if (!ok) {
+        fprintf(stderr, "ffi smoke test failed (set returned %d)\n", status);
+        return 1;
+    }
+    printf("ok\n");
+    return 0;
+}
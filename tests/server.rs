@@ -0,0 +1,988 @@
+//! End-to-end tests of the socket server: each test runs the iosync server on
+//! its own socket, backed by a mock clipboard, and talks to it over the socket.
+#![cfg(target_os = "linux")]
+
+use arboard::ImageData;
+use image::ImageEncoder;
+use ssh_clipboard::mmap::read_mapped;
+use ssh_clipboard::{
+    init_log, Clipboard, ClipboardClient, ClipboardServer, ConflictStrategy, Error, Level,
+    NormOpts, Options, Subscription, UnixTransport, WireFormat, IMAGE_PNG, TEXT_HTML, TEXT_PLAIN,
+};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+/// One call made on the mock clipboard.
+#[derive(Debug, PartialEq)]
+enum Call {
+    GetText,
+    SetText(String),
+    GetImage,
+    SetImage { width: usize, height: usize },
+}
+
+/// A clipboard that holds text in memory and records every call. Clones share
+/// the same contents and call log.
+#[derive(Clone, Default)]
+struct MockClipboard {
+    text: Arc<Mutex<Option<String>>>,
+    calls: Arc<Mutex<Vec<Call>>>,
+}
+
+impl MockClipboard {
+    fn calls(&self) -> Vec<Call> {
+        std::mem::take(&mut self.calls.lock().unwrap())
+    }
+}
+
+impl Clipboard for MockClipboard {
+    fn get_text(&mut self) -> Result<String, arboard::Error> {
+        self.calls.lock().unwrap().push(Call::GetText);
+        let text = self.text.lock().unwrap().clone();
+        text.ok_or(arboard::Error::ContentNotAvailable)
+    }
+
+    fn set_text(&mut self, text: &str) -> Result<(), arboard::Error> {
+        self.calls
+            .lock()
+            .unwrap()
+            .push(Call::SetText(text.to_string()));
+        *self.text.lock().unwrap() = Some(text.to_string());
+        Ok(())
+    }
+
+    fn get_image(&mut self) -> Result<ImageData<'static>, arboard::Error> {
+        self.calls.lock().unwrap().push(Call::GetImage);
+        Err(arboard::Error::ContentNotAvailable)
+    }
+
+    fn set_image(&mut self, image: ImageData) -> Result<(), arboard::Error> {
+        self.calls.lock().unwrap().push(Call::SetImage {
+            width: image.width,
+            height: image.height,
+        });
+        *self.text.lock().unwrap() = None;
+        Ok(())
+    }
+}
+
+/// A server running in the background on a socket in its own temporary
+/// directory. The server thread is left running when the test ends.
+struct TestServer {
+    dir: TempDir,
+    clipboard: MockClipboard,
+}
+
+impl TestServer {
+    fn start() -> TestServer {
+        TestServer::start_with(|_| {})
+    }
+
+    /// Start a server with options adjusted by `configure`.
+    fn start_with(configure: impl FnOnce(&mut Options)) -> TestServer {
+        init_log(None, 0, 0, Level::Error);
+        let dir = tempfile::tempdir().unwrap();
+        let clipboard = MockClipboard::default();
+        let mut opts = options(&dir);
+        configure(&mut opts);
+        let server = ClipboardServer::with_clipboard(opts, Box::new(clipboard.clone()));
+        thread::spawn(move || server.run());
+        TestServer { dir, clipboard }
+    }
+
+    /// A client that retries until the server is listening.
+    fn client(&self) -> ClipboardClient<UnixTransport> {
+        let opts = options(&self.dir);
+        ClipboardClient::new(UnixTransport::new(&opts), &opts)
+    }
+}
+
+fn options(dir: &TempDir) -> Options {
+    Options {
+        socket_path: dir.path().join("iosync.sock").display().to_string(),
+        abstract_socket: false,
+        socket_mode: 0o600,
+        tcp_addr: None,
+        metrics_socket: None,
+        pid_path: dir.path().join("iosync.pid"),
+        state_path: None,
+        image_out: None,
+        audit_log: None,
+        log_path: None,
+        log_max_bytes: 0,
+        log_backups: 0,
+        log_level: Level::Error,
+        poll_interval: Duration::from_millis(100),
+        debounce: Duration::ZERO,
+        history_size: 5,
+        clipboard_ttl: None,
+        heartbeat_interval: None,
+        heartbeat_timeout: None,
+        auth_key: None,
+        allow_uids: Vec::new(),
+        retry_interval: Duration::from_millis(10),
+        retry_count: 200,
+        verbose: false,
+        max_bytes: 1024 * 1024,
+        compress_threshold: 64 * 1024,
+        sync_prefix: "CLIPBOARD_SYNC:".to_string(),
+        wire_format: WireFormat::Json,
+        skip_patterns: Vec::new(),
+        mask_patterns: Vec::new(),
+        normalize: NormOpts::default(),
+        tracking_params: Vec::new(),
+        conflict_strategy: ConflictStrategy::LastWriterWins,
+        rate_limit: 0,
+        global_rate_limit: 0,
+        max_bandwidth_bps: None,
+        shutdown_timeout: Duration::from_secs(1),
+        takeover: false,
+        mmap_path: None,
+        dbus: false,
+    }
+}
+
+#[test]
+fn set_updates_the_clipboard() {
+    let server = TestServer::start();
+    let client = server.client();
+    client.set("hello").unwrap();
+    assert_eq!(
+        server.clipboard.calls(),
+        vec![Call::SetText("hello".to_string())]
+    );
+
+    // Setting the same text again is not a change.
+    client.set("hello").unwrap();
+    assert_eq!(server.clipboard.calls(), vec![]);
+}
+
+#[test]
+fn get_reads_the_clipboard() {
+    let server = TestServer::start();
+    let client = server.client();
+    *server.clipboard.text.lock().unwrap() = Some("copied locally".to_string());
+    assert_eq!(client.get().unwrap(), "copied locally");
+    assert_eq!(server.clipboard.calls(), vec![Call::GetText]);
+}
+
+#[test]
+fn get_returns_the_last_set_text() {
+    let server = TestServer::start();
+    let client = server.client();
+    client.set("round trip").unwrap();
+    assert_eq!(client.get().unwrap(), "round trip");
+    assert_eq!(
+        server.clipboard.calls(),
+        vec![Call::SetText("round trip".to_string()), Call::GetText]
+    );
+}
+
+#[test]
+fn set_data_sets_an_image() {
+    let server = TestServer::start();
+    let client = server.client();
+    let mut png = Vec::new();
+    image::codecs::png::PngEncoder::new(&mut png)
+        .write_image(&[0xff; 2 * 3 * 4], 2, 3, image::ExtendedColorType::Rgba8)
+        .unwrap();
+    client.set_data(IMAGE_PNG, &png).unwrap();
+    assert_eq!(
+        server.clipboard.calls(),
+        vec![Call::SetImage {
+            width: 2,
+            height: 3
+        }]
+    );
+    assert_eq!(client.get_data(IMAGE_PNG).unwrap(), png);
+}
+
+#[test]
+fn set_image_writes_the_png_out() {
+    let out_dir = tempfile::tempdir().unwrap();
+    let out = out_dir.path().join("clipboard.png");
+    let server = TestServer::start_with(|opts| opts.image_out = Some(out.clone()));
+    let client = server.client();
+    let mut png = Vec::new();
+    image::codecs::png::PngEncoder::new(&mut png)
+        .write_image(&[0; 4 * 4], 4, 1, image::ExtendedColorType::Rgba8)
+        .unwrap();
+    assert!(client.set_image(b"not a png").is_err());
+    client.set_image(&png).unwrap();
+    assert_eq!(
+        server.clipboard.calls(),
+        vec![Call::SetImage {
+            width: 4,
+            height: 1
+        }]
+    );
+    assert_eq!(std::fs::read(&out).unwrap(), png);
+}
+
+#[test]
+fn get_html_returns_html_content() {
+    let server = TestServer::start();
+    let client = server.client();
+    assert_eq!(client.get_html().unwrap(), "");
+    client.set_data(TEXT_HTML, b"<b>bold</b>").unwrap();
+    assert_eq!(client.get_html().unwrap(), "<b>bold</b>");
+}
+
+#[test]
+fn xclip_prints_the_message_as_json() {
+    let server = TestServer::start();
+    server.client().set("hello").unwrap();
+    let msg = server.client().get_message().unwrap();
+    assert_eq!(
+        (msg.content_type.as_str(), msg.data),
+        ("text/plain", b"hello".to_vec())
+    );
+
+    let output = run_xclip(&server, &["-o", "--format", "json"], "");
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["content_type"], "text/plain");
+    assert_eq!(json["data"], "aGVsbG8=");
+    assert_eq!(json["sha256"].as_str().unwrap().len(), 64);
+}
+
+#[test]
+fn xclip_peek_prints_one_line() {
+    let server = TestServer::start();
+    server.client().set("two\nlines").unwrap();
+    let output = run_xclip(&server, &["--peek"], "");
+    assert_eq!(output.stdout, b"two\\nlines (9 bytes, text/plain)\n");
+
+    let output = run_xclip(&server, &["--peek", "--max-chars", "3"], "");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout, "two\u{2026} (9 bytes, text/plain)\n");
+}
+
+#[test]
+fn xclip_qr_draws_short_text_and_refuses_long_text() {
+    let server = TestServer::start();
+    server.client().set("https://example.com").unwrap();
+    let output = run_xclip(&server, &["--qr"], "");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let widths: Vec<_> = stdout.lines().map(|line| line.chars().count()).collect();
+    assert!(widths.len() > 10 && widths.iter().all(|&width| width == widths[0]));
+
+    server.client().set(&"x".repeat(3000)).unwrap();
+    let output = run_xclip(&server, &["--qr"], "");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("holds 3000 bytes"), "{}", stderr);
+}
+
+#[test]
+fn get_format_returns_the_first_available_type() {
+    let server = TestServer::start();
+    let client = server.client();
+    client.set("plain").unwrap();
+    let types = [IMAGE_PNG, TEXT_HTML, "text/plain"];
+    assert_eq!(
+        client.get_format(&types).unwrap(),
+        Some(("text/plain".to_string(), b"plain".to_vec()))
+    );
+    client.set_data(TEXT_HTML, b"<i>rich</i>").unwrap();
+    assert_eq!(
+        client.get_format(&types).unwrap(),
+        Some((TEXT_HTML.to_string(), b"<i>rich</i>".to_vec()))
+    );
+    assert_eq!(client.get_format(&[IMAGE_PNG]).unwrap(), None);
+}
+
+#[test]
+fn selections_are_kept_apart() {
+    let server = TestServer::start();
+    let client = server.client();
+    client.set_selection("PRIMARY", "selected").unwrap();
+    client.set("copied").unwrap();
+    client.set("PRIMARY is not a selection here").unwrap();
+    assert_eq!(client.get_selection("PRIMARY").unwrap(), "selected");
+    assert_eq!(client.get_primary().unwrap(), "selected");
+    assert_eq!(client.get_selection("SECONDARY").unwrap(), "");
+    assert_eq!(
+        client.get_selection("CLIPBOARD").unwrap(),
+        "PRIMARY is not a selection here"
+    );
+    // Only CLIPBOARD reaches the clipboard.
+    assert_eq!(
+        server.clipboard.calls(),
+        vec![
+            Call::SetText("copied".to_string()),
+            Call::SetText("PRIMARY is not a selection here".to_string()),
+            Call::GetText,
+        ]
+    );
+}
+
+#[test]
+fn named_slots_hold_text_apart_from_the_clipboard() {
+    let server = TestServer::start();
+    let client = server.client();
+    client.set_slot("a", "first register").unwrap();
+    client.set_slot("slot_2", "x").unwrap();
+    client.set_selection("PRIMARY", "sel").unwrap();
+    assert_eq!(client.get_slot("a").unwrap(), "first register");
+    assert_eq!(client.get_slot("unset").unwrap(), "");
+    assert!(client.set_slot("no-dashes", "x").is_err());
+    assert!(client.get_slot(&"a".repeat(33)).is_err());
+    assert!(client.set_slot("CLIPBOARD", "x").is_err());
+    assert_eq!(
+        client.list().unwrap().into_iter().collect::<Vec<_>>(),
+        [
+            ("PRIMARY".to_string(), 3),
+            ("a".to_string(), 14),
+            ("slot_2".to_string(), 1)
+        ]
+    );
+    assert_eq!(server.clipboard.calls(), vec![]);
+}
+
+#[test]
+fn subscribers_get_each_change_of_their_selection() {
+    let server = TestServer::start();
+    let client = server.client();
+    let mut clipboard = client.subscribe("CLIPBOARD").unwrap();
+    let mut primary = client.subscribe("PRIMARY").unwrap();
+    client.set("one").unwrap();
+    client.set_selection("PRIMARY", "sel").unwrap();
+    client.set("two").unwrap();
+    let next = |subscription: &mut Subscription<_>| subscription.next().unwrap().unwrap().data;
+    assert_eq!(next(&mut clipboard), b"one");
+    assert_eq!(next(&mut clipboard), b"two");
+    assert_eq!(next(&mut primary), b"sel");
+    clipboard.unsubscribe().unwrap();
+    client.set("three").unwrap();
+    primary.unsubscribe().unwrap();
+    assert!(client.subscribe("no-dashes").is_err());
+}
+
+#[test]
+fn clear_empties_the_clipboard() {
+    let server = TestServer::start();
+    let client = server.client();
+    client.set("secret").unwrap();
+    client.clear().unwrap();
+    assert_eq!(
+        server.clipboard.calls(),
+        vec![
+            Call::SetText("secret".to_string()),
+            Call::SetText(String::new())
+        ]
+    );
+}
+
+#[test]
+fn status_counts_synced_messages() {
+    let server = TestServer::start();
+    let client = server.client();
+    client.set("one").unwrap();
+    client.set("two").unwrap();
+    // The server counts a connection out after replying, so the SETs may
+    // still be closing when STATUS arrives; only its own should remain.
+    let mut status = serde_json::Value::Null;
+    for _ in 0..100 {
+        status = serde_json::from_slice(&client.request("STATUS").unwrap()).unwrap();
+        if status["clients_connected"] == 1 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(status["clients_connected"], 1);
+    assert_eq!(status["messages_synced"], 2);
+    assert!(status["bytes_received"].as_u64().unwrap() > 0);
+    assert!(status["last_sync_ms"].as_u64().is_some());
+}
+
+#[test]
+fn wipe_empties_the_clipboard_history_selections_and_slots() {
+    let server = TestServer::start();
+    let client = server.client();
+    client.set("one").unwrap();
+    client.set("two").unwrap();
+    client.set_primary("selected").unwrap();
+    client.set_slot("a", "register").unwrap();
+    client.wipe().unwrap();
+    assert!(client.history_all().unwrap().is_empty());
+    assert_eq!(client.get_primary().unwrap(), "");
+    assert_eq!(client.get_slot("a").unwrap(), "");
+    assert!(client.list().unwrap().is_empty());
+    assert_eq!(
+        server.clipboard.calls().last(),
+        Some(&Call::SetText(String::new()))
+    );
+    assert_eq!(client.get().unwrap(), "");
+
+    client.set("three").unwrap();
+    let output = run_xclip(&server, &["--wipe"], "");
+    assert!(output.status.success());
+    assert!(client.history_all().unwrap().is_empty());
+}
+
+#[test]
+fn search_finds_history_entries_by_text_or_regex() {
+    let server = TestServer::start();
+    let client = server.client();
+    for text in ["Hello world", "goodbye", "say HELLO"] {
+        client.set(text).unwrap();
+    }
+    let indices = |query: &str, regex: bool| -> Vec<usize> {
+        let matches = client.search(query, regex).unwrap();
+        matches.iter().map(|found| found.index).collect()
+    };
+    assert_eq!(indices("hello", false), [0, 2]);
+    assert_eq!(indices("^[a-z]+$", true), [1]);
+    assert!(indices("nothing", false).is_empty());
+    assert!(client.search("(", true).is_err());
+
+    let output = run_xclip(&server, &["--search", "bye"], "");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let fields: Vec<_> = stdout.trim_end().split('\t').collect();
+    assert_eq!(fields[0], "1");
+    assert_eq!(fields[2], "goodbye (7 bytes, text/plain)");
+}
+
+#[test]
+fn exported_history_imports_into_another_server() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let first = TestServer::start();
+    first.client().set("one").unwrap();
+    first.client().set("two").unwrap();
+    let export = first.dir.path().join("history.json");
+    let output = run_xclip(&first, &["--export", export.to_str().unwrap()], "");
+    assert!(output.status.success());
+    // The export holds the clipboard in cleartext.
+    let mode = std::fs::metadata(&export).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    let second = TestServer::start();
+    second.client().set("three").unwrap();
+    let output = run_xclip(&second, &["--import", export.to_str().unwrap()], "");
+    assert!(output.status.success());
+    let client = second.client();
+    let entries = client.history_all().unwrap();
+    let texts: Vec<_> = entries.iter().map(|msg| msg.as_text().unwrap()).collect();
+    assert_eq!(texts, ["two", "one", "three"]);
+    assert_eq!(entries[..2], first.client().history_all().unwrap()[..]);
+    assert!(entries.iter().all(|msg| msg.sent_at_ms > 0));
+    // Importing doesn't change the clipboard.
+    assert_eq!(client.get().unwrap(), "three");
+}
+
+#[test]
+fn xclip_stats_summarizes_the_status() {
+    let server = TestServer::start();
+    server.client().set("one").unwrap();
+    server.client().set("two").unwrap();
+    let status = server.client().status().unwrap();
+    assert_eq!(status.messages_synced, 2);
+
+    let output = run_xclip(&server, &["--stats"], "");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let fields: Vec<_> = stdout.trim_end().split(" | ").collect();
+    assert_eq!(fields.len(), 5, "{}", stdout);
+    assert!(fields[0].starts_with("Uptime: "), "{}", stdout);
+    assert_eq!(fields[1], "Syncs: 2");
+    assert!(fields[2].starts_with("Sent: "), "{}", stdout);
+    assert!(fields[4].ends_with("s ago"), "{}", stdout);
+}
+
+#[test]
+fn every_waiting_client_sees_a_change() {
+    let server = TestServer::start();
+    let client = server.client();
+    client.set("before").unwrap();
+    let waiters: Vec<_> = (0..3)
+        .map(|_| {
+            let client = server.client();
+            thread::spawn(move || client.wait(Duration::from_secs(10)).unwrap())
+        })
+        .collect();
+    // Keep changing the clipboard until every waiter has seen a change, since
+    // there is no telling when each one has started waiting.
+    for n in 0.. {
+        if waiters.iter().all(|waiter| waiter.is_finished()) {
+            break;
+        }
+        client.set(&format!("after {}", n)).unwrap();
+        thread::sleep(Duration::from_millis(50));
+    }
+    for waiter in waiters {
+        assert!(waiter.join().unwrap().starts_with("after "));
+    }
+}
+
+#[test]
+fn set_ignores_text_matching_a_skip_pattern() {
+    let server = TestServer::start_with(|opts| {
+        opts.skip_patterns = vec![regex::Regex::new("^hunter[0-9]+$").unwrap()];
+    });
+    let client = server.client();
+    client.set("public").unwrap();
+    client.set("hunter2").unwrap();
+    assert_eq!(client.get().unwrap(), "public");
+    assert_eq!(
+        server.clipboard.calls(),
+        vec![Call::SetText("public".to_string()), Call::GetText]
+    );
+}
+
+#[test]
+fn mapped_clipboard_mirrors_text_for_local_clients() {
+    let map_dir = tempfile::tempdir().unwrap();
+    let map_path = map_dir.path().join("clipboard.map");
+    let server = TestServer::start_with(|opts| opts.mmap_path = Some(map_path.clone()));
+    server.client().set("mapped").unwrap();
+
+    // The file is updated right after the change is published.
+    let mut mapped = None;
+    for _ in 0..100 {
+        mapped = read_mapped(&map_path).ok().flatten();
+        if mapped.is_some() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(mapped.as_deref(), Some("mapped"));
+    let opts = Options {
+        mmap_path: Some(map_path.clone()),
+        ..options(&server.dir)
+    };
+    let client = ClipboardClient::new(UnixTransport::new(&opts), &opts);
+    assert_eq!(client.get().unwrap(), "mapped");
+    // A reader never asks the server for the mapped text.
+    assert_eq!(
+        server.clipboard.calls(),
+        vec![Call::SetText("mapped".to_string())]
+    );
+
+    // Anything but text is left to the socket.
+    let mut png = Vec::new();
+    image::codecs::png::PngEncoder::new(&mut png)
+        .write_image(&[0xff; 4], 1, 1, image::ExtendedColorType::Rgba8)
+        .unwrap();
+    client.set_data(IMAGE_PNG, &png).unwrap();
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(read_mapped(&map_path).unwrap(), None);
+}
+
+#[test]
+fn audit_log_records_changes_without_content() {
+    let audit_dir = tempfile::tempdir().unwrap();
+    let audit_log = audit_dir.path().join("audit.log");
+    let server = TestServer::start_with(|opts| {
+        opts.audit_log = Some(audit_log.clone());
+        opts.max_bytes = 16;
+    });
+    let client = server.client();
+    client.set("secret").unwrap();
+    // Neither a repeat nor a rejected change is recorded.
+    client.set("secret").unwrap();
+    assert!(client.set(&"x".repeat(17)).is_err());
+    client.clear().unwrap();
+
+    let contents = std::fs::read_to_string(&audit_log).unwrap();
+    assert!(!contents.contains("secret"));
+    let records: Vec<serde_json::Value> = contents
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0]["op"], "SET");
+    assert_eq!(records[0]["len"], 6);
+    assert_eq!(
+        records[0]["content_hash"],
+        "sha256:2bb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b"
+    );
+    assert_eq!(records[0]["client_pid"], std::process::id());
+    assert_eq!(records[0]["uid"], unsafe { libc::getuid() });
+    assert_eq!(records[1]["op"], "DELETE");
+}
+
+#[test]
+fn set_is_rate_limited_per_client() {
+    let server = TestServer::start_with(|opts| opts.rate_limit = 2);
+    let client = server.client();
+    client.set("one").unwrap();
+    client.set("two").unwrap();
+    assert!(matches!(client.set("three"), Err(Error::RateLimited)));
+    // Clearing changes the clipboard too.
+    assert!(matches!(client.clear(), Err(Error::RateLimited)));
+    assert!(matches!(client.wipe(), Err(Error::RateLimited)));
+    // Reads are never limited.
+    assert_eq!(client.get().unwrap(), "two");
+    thread::sleep(Duration::from_millis(600));
+    client.set("four").unwrap();
+}
+
+#[test]
+fn global_rate_limit_applies_to_all_clients() {
+    let server = TestServer::start_with(|opts| opts.global_rate_limit = 3);
+    let sets: Vec<_> = (0..6)
+        .map(|n| {
+            let client = server.client();
+            thread::spawn(move || client.set(&n.to_string()))
+        })
+        .collect();
+    let limited = sets
+        .into_iter()
+        .map(|set| set.join().unwrap())
+        .filter(|result| matches!(result, Err(Error::RateLimited)))
+        .count();
+    assert!(limited >= 2, "only {} of 6 sets were limited", limited);
+}
+
+#[test]
+fn server_replaces_a_stale_socket_but_not_a_live_one() {
+    let dir = tempfile::tempdir().unwrap();
+    let opts = options(&dir);
+    // A socket file nobody listens on, as left by a crash.
+    drop(std::os::unix::net::UnixListener::bind(&opts.socket_path).unwrap());
+    let server = ClipboardServer::new(options(&dir));
+    thread::spawn(move || server.run());
+    let client = ClipboardClient::new(UnixTransport::new(&opts), &opts);
+    client.set("live").unwrap();
+
+    let second = ClipboardServer::new(Options {
+        pid_path: dir.path().join("second.pid"),
+        ..options(&dir)
+    });
+    let err = second.run().unwrap_err().to_string();
+    assert!(
+        err.contains(&format!("in use by process {}", std::process::id())),
+        "{}",
+        err
+    );
+    assert_eq!(client.get().unwrap(), "live");
+}
+
+#[test]
+fn restarted_server_restores_the_clipboard() {
+    let state_dir = tempfile::tempdir().unwrap();
+    let state_path = state_dir.path().join("last.json");
+    let first = TestServer::start_with(|opts| opts.state_path = Some(state_path.clone()));
+    first.client().set("kept").unwrap();
+
+    let second = TestServer::start_with(|opts| opts.state_path = Some(state_path.clone()));
+    let client = second.client();
+    // No mock clipboard text, so GET falls back to the restored message.
+    assert_eq!(client.get().unwrap(), "kept");
+    // Setting the restored text again is not a change.
+    client.set("kept").unwrap();
+    assert_eq!(second.clipboard.calls(), vec![Call::GetText]);
+}
+
+#[test]
+fn server_listens_on_a_socket_passed_by_systemd() {
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::UnixListener;
+    use std::os::unix::process::CommandExt;
+    use std::process::{Command, Stdio};
+
+    let dir = tempfile::tempdir().unwrap();
+    let opts = options(&dir);
+    let listener = UnixListener::bind(&opts.socket_path).unwrap();
+    let fd = listener.as_raw_fd();
+    // The shell execs the server, so $$ is the server's PID as systemd sets it.
+    let mut command = Command::new("sh");
+    command
+        .args(["-c", "LISTEN_PID=$$ LISTEN_FDS=1 exec \"$0\" \"$@\""])
+        .arg(env!("CARGO_BIN_EXE_ssh-clipboard"))
+        .args(["iosync", "--socket", &opts.socket_path, "--no-log"])
+        .env("HOME", dir.path())
+        .stderr(Stdio::null());
+    unsafe {
+        command.pre_exec(move || match libc::dup2(fd, 3) {
+            -1 => Err(std::io::Error::last_os_error()),
+            _ => Ok(()),
+        });
+    }
+    let mut server = command.spawn().unwrap();
+    drop(listener);
+
+    let client = ClipboardClient::new(UnixTransport::new(&opts), &opts);
+    client.set("activated").unwrap();
+    assert_eq!(client.get().unwrap(), "activated");
+    unsafe { libc::kill(server.id() as libc::pid_t, libc::SIGTERM) };
+    server.wait().unwrap();
+    // The socket file belongs to systemd and outlives the server.
+    assert!(std::path::Path::new(&opts.socket_path).exists());
+}
+
+#[test]
+fn servers_on_different_sockets_run_side_by_side() {
+    use std::process::{Child, Command, Stdio};
+
+    /// Stops the servers even when an assertion fails.
+    struct Servers(Vec<Child>);
+    impl Drop for Servers {
+        fn drop(&mut self) {
+            for server in &mut self.0 {
+                unsafe { libc::kill(server.id() as libc::pid_t, libc::SIGTERM) };
+                let _ = server.wait();
+            }
+        }
+    }
+
+    let dirs = [tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap()];
+    let mut servers = Servers(Vec::new());
+    for dir in &dirs {
+        let opts = options(dir);
+        let server = Command::new(env!("CARGO_BIN_EXE_ssh-clipboard"))
+            .args(["iosync", "--socket", &opts.socket_path, "--no-log"])
+            .env("HOME", dir.path())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        servers.0.push(server);
+    }
+    for (dir, text) in dirs.iter().zip(["first", "second"]) {
+        let opts = options(dir);
+        let client = ClipboardClient::new(UnixTransport::new(&opts), &opts);
+        let mut set = client.set(text);
+        for _ in 0..100 {
+            if set.is_ok() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+            set = client.set(text);
+        }
+        set.unwrap();
+        assert_eq!(client.get().unwrap(), text);
+        for suffix in ["pid", "metrics"] {
+            let path = format!("{}.{}", opts.socket_path, suffix);
+            assert!(std::path::Path::new(&path).exists(), "no {}", path);
+        }
+    }
+    for server in &mut servers.0 {
+        assert!(server.try_wait().unwrap().is_none());
+    }
+}
+
+#[test]
+fn no_metrics_leaves_the_metrics_socket_unbound() {
+    use std::process::{Command, Stdio};
+
+    let dir = tempfile::tempdir().unwrap();
+    let opts = options(&dir);
+    let config = dir.path().join("config.toml");
+    std::fs::write(&config, "metrics_socket = \"\"\n").unwrap();
+    for args in [
+        &["--no-metrics"][..],
+        &["--config", config.to_str().unwrap()],
+    ] {
+        let mut server = Command::new(env!("CARGO_BIN_EXE_ssh-clipboard"))
+            .args(["iosync", "--socket", &opts.socket_path, "--no-log"])
+            .args(args)
+            .env("HOME", dir.path())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let client = ClipboardClient::new(UnixTransport::new(&opts), &opts);
+        let set = client.set("hello");
+        unsafe { libc::kill(server.id() as libc::pid_t, libc::SIGTERM) };
+        server.wait().unwrap();
+        set.unwrap();
+        let metrics = format!("{}.metrics", opts.socket_path);
+        assert!(!std::path::Path::new(&metrics).exists());
+    }
+}
+
+/// Run the binary through a link named `xclip`, as neovim's provider does.
+fn run_xclip(server: &TestServer, args: &[&str], stdin: &str) -> std::process::Output {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    let xclip = server.dir.path().join("xclip");
+    if !xclip.exists() {
+        std::os::unix::fs::symlink(env!("CARGO_BIN_EXE_ssh-clipboard"), &xclip).unwrap();
+    }
+    let socket = options(&server.dir).socket_path;
+    let mut child = Command::new(xclip)
+        .args(["--socket", &socket, "--no-log"])
+        .args(args)
+        .env("HOME", server.dir.path())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn xclip_mode_works_as_a_neovim_provider() {
+    let server = TestServer::start();
+    // Wait for the server before running the binary.
+    server.client().set("").unwrap();
+    server.clipboard.calls();
+
+    let copy = ["--neovim-compat", "-quiet", "-i", "-selection", "clipboard"];
+    let output = run_xclip(&server, &copy, "a linewise yank\n");
+    assert!(output.status.success());
+    assert_eq!(
+        server.clipboard.calls(),
+        vec![Call::SetText("a linewise yank\n".to_string())]
+    );
+
+    let paste = ["--neovim-compat", "-o", "-selection", "clipboard"];
+    let output = run_xclip(&server, &paste, "");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"a linewise yank\n");
+
+    let copy = ["--neovim-compat", "-quiet", "-i", "-selection", "primary"];
+    assert!(run_xclip(&server, &copy, "selected").status.success());
+    let paste = ["--neovim-compat", "-o", "-selection", "primary"];
+    assert_eq!(run_xclip(&server, &paste, "").stdout, b"selected");
+}
+
+#[test]
+fn xclip_dry_run_reads_but_does_not_write() {
+    let server = TestServer::start();
+    server.client().set("current").unwrap();
+    server.clipboard.calls();
+
+    let output = run_xclip(&server, &["--dry-run"], "new");
+    assert!(output.status.success());
+    assert_eq!(output.stderr, b"[dry-run] would send: SET new\n");
+    assert_eq!(server.clipboard.calls(), vec![]);
+
+    let output = run_xclip(&server, &["--dry-run", "-o"], "");
+    assert!(output.status.success());
+    assert_eq!(output.stderr, b"[dry-run] would send: GET\n");
+    assert_eq!(output.stdout, b"current\n");
+}
+
+#[test]
+fn xclip_test_pings_and_fails_with_1_without_a_server() {
+    let server = TestServer::start();
+    server.client().set("").unwrap();
+    let output = run_xclip(&server, &["--test"], "");
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout).unwrap().ends_with(" ms\n"));
+
+    std::fs::remove_file(options(&server.dir).socket_path).unwrap();
+    let output = run_xclip(&server, &["--test"], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .starts_with("xclip: cannot connect"));
+}
+
+#[test]
+fn reads_with_the_wrong_auth_key_fail_instead_of_returning_the_error() {
+    use ssh_clipboard::ffi;
+    use std::ffi::CString;
+
+    let server = TestServer::start_with(|opts| opts.auth_key = Some(vec![1; 32]));
+    let mut opts = options(&server.dir);
+    opts.auth_key = Some(vec![1; 32]);
+    ClipboardClient::new(UnixTransport::new(&opts), &opts)
+        .set("secret")
+        .unwrap();
+    let wrong = ClipboardClient::with_auth_key(UnixTransport::new(&opts), Some(vec![2; 32]));
+    assert!(matches!(wrong.get(), Err(Error::AuthFailure)));
+    assert!(matches!(wrong.get_html(), Err(Error::AuthFailure)));
+    assert!(matches!(
+        wrong.get_data(TEXT_PLAIN),
+        Err(Error::AuthFailure)
+    ));
+
+    let output = run_xclip(&server, &["-o", "--auth-key", &"02".repeat(32)], "");
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+
+    let socket = CString::new(opts.socket_path.clone()).unwrap();
+    let key = CString::new("02".repeat(32)).unwrap();
+    unsafe {
+        let client = ffi::ssh_clipboard_client_new(socket.as_ptr(), key.as_ptr());
+        assert!(!client.is_null());
+        assert!(ffi::ssh_clipboard_get(client).is_null());
+        ffi::ssh_clipboard_free(client);
+    }
+}
+
+#[test]
+fn content_that_looks_like_an_error_reply_is_returned_as_is() {
+    let server = TestServer::start();
+    let client = server.client();
+    for text in ["TIMEOUT", "TOO_LARGE", "Unknown command", "No such entry"] {
+        client.set(text).unwrap();
+        assert_eq!(client.get().unwrap(), text);
+        assert_eq!(client.history(0).unwrap().as_text(), Some(text));
+        let format = client.get_format(&[TEXT_PLAIN]).unwrap();
+        assert_eq!(
+            format,
+            Some((TEXT_PLAIN.to_string(), text.as_bytes().to_vec()))
+        );
+        client.set_slot("a", text).unwrap();
+        assert_eq!(client.get_slot("a").unwrap(), text);
+    }
+    // Real errors are still errors.
+    assert!(client.wait(Duration::from_millis(10)).is_err());
+    assert!(client.history(99).is_err());
+}
+
+#[test]
+fn xclip_verbose_prints_commands_and_replies() {
+    let server = TestServer::start();
+    server.client().set("").unwrap();
+
+    let output = run_xclip(&server, &["--verbose"], "hello");
+    assert!(output.status.success());
+    assert_eq!(output.stderr, b"> SET hello\n< +OK\n");
+
+    let long = "x".repeat(100);
+    let output = run_xclip(&server, &["--verbose"], &long);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(
+        stderr.lines().next().unwrap(),
+        format!("> SET {}...", &long[..76])
+    );
+}
+
+#[test]
+fn diagnose_reports_each_check_and_fails_if_any_does() {
+    let server = TestServer::start();
+    server.client().set("").unwrap();
+
+    let socket = options(&server.dir).socket_path;
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_ssh-clipboard"))
+        .args(["iosync", "--diagnose", "--socket", &socket, "--no-log"])
+        .env("HOME", server.dir.path())
+        .env_remove("SSH_CONNECTION")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(output.status.code(), Some(1), "{}", stdout);
+    assert!(
+        stdout.contains("\u{2713} logging is disabled\n"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains(&format!(
+            "\u{2713} a server answers on the Unix socket {} in",
+            socket
+        )),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("\u{2717} SSH_CONNECTION is not set"),
+        "{}",
+        stdout
+    );
+}